@@ -0,0 +1,147 @@
+//! End-to-end and per-stage throughput, run with `cargo bench`
+//!
+//! pipeline sizes can be limited with `BENCH_ROWS`, e.g. `BENCH_ROWS=100000 cargo bench`
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use krct_async::account::Account;
+use krct_async::generator::{Generator, GeneratorConfig};
+use krct_async::primitives::*;
+use krct_async::service::Service;
+use krct_async::transaction::{InputTransaction, Transaction};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::path::PathBuf;
+use tokio::sync::mpsc;
+
+fn input_transactions(config: GeneratorConfig) -> Vec<InputTransaction> {
+    let mut data = Vec::new();
+    Generator::new(config)
+        .unwrap()
+        .write_csv(&mut data)
+        .unwrap();
+    csv::Reader::from_reader(data.as_slice())
+        .deserialize()
+        .collect::<Result<_, _>>()
+        .unwrap()
+}
+
+fn bench_parse(c: &mut Criterion) {
+    let inputs = input_transactions(GeneratorConfig {
+        transactions: 10_000,
+        whitespace_rate: 0.1,
+        ..Default::default()
+    });
+
+    let mut group = c.benchmark_group("parse");
+    group.throughput(Throughput::Elements(inputs.len() as u64));
+    group.bench_function("Transaction::try_from", |b| {
+        b.iter_batched(
+            || inputs.clone(),
+            |inputs| {
+                inputs
+                    .into_iter()
+                    .filter_map(|input| Transaction::try_from(input).ok())
+                    .count()
+            },
+            criterion::BatchSize::LargeInput,
+        )
+    });
+    group.finish();
+}
+
+fn bench_account(c: &mut Criterion) {
+    let txs = input_transactions(GeneratorConfig {
+        clients: 1,
+        transactions: 10_000,
+        ..Default::default()
+    })
+    .into_iter()
+    .map(|input| Transaction::try_from(input).unwrap())
+    .collect::<Vec<_>>();
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+
+    let mut group = c.benchmark_group("account");
+    group.throughput(Throughput::Elements(txs.len() as u64));
+    group.bench_function("Account::process", |b| {
+        b.to_async(&runtime).iter(|| async {
+            let mut account = Account::new(1).set_overdraft_limit(Coin::MAX);
+            for tx in &txs {
+                account.process(tx).await.unwrap();
+            }
+            account
+        })
+    });
+    group.finish();
+}
+
+/// Generated input file, reused between runs with the same generator config
+fn input_file(rows: u64, clients: AccountID) -> PathBuf {
+    let config = GeneratorConfig {
+        clients,
+        transactions: rows,
+        seed: 1,
+        ..Default::default()
+    };
+    // every field of the config is in the key, rates are floats, so their debug output is hashed
+    let mut hasher = DefaultHasher::new();
+    format!("{:?}", config).hash(&mut hasher);
+    let path = std::env::temp_dir().join(format!(
+        "krct_async_bench_{}_{}_{:016x}.csv",
+        rows,
+        clients,
+        hasher.finish()
+    ));
+    if !path.exists() {
+        // written to a file of this process and moved in place, so an interrupted or concurrent run never leaves a partial input
+        let tmp = path.with_extension(format!("{}.tmp", std::process::id()));
+        let mut file = std::io::BufWriter::new(std::fs::File::create(&tmp).unwrap());
+        Generator::new(config)
+            .unwrap()
+            .write_csv(&mut file)
+            .unwrap();
+        std::io::Write::flush(&mut file).unwrap();
+        std::fs::rename(&tmp, &path).unwrap();
+    }
+    path
+}
+
+async fn run_pipeline(path: PathBuf) -> usize {
+    let (sender, receiver) = mpsc::channel(CHANNEL_BUUFER_SIZE);
+    let reader = tokio::spawn(async move {
+        run_reader(
+            path.into(),
+            sender,
+            &mut std::io::sink(),
+            &ReaderConfig::default(),
+        )
+        .await
+    });
+    let mut service = Service::new(receiver);
+    service.run().await.unwrap();
+    reader.await.unwrap().unwrap();
+    service.get_accounts().await.len()
+}
+
+fn bench_pipeline(c: &mut Criterion) {
+    let rows = std::env::var("BENCH_ROWS")
+        .map(|rows| rows.split(',').map(|n| n.trim().parse().unwrap()).collect())
+        .unwrap_or_else(|_| vec![1_000_000u64, 10_000_000]);
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+
+    let mut group = c.benchmark_group("pipeline");
+    group.sample_size(10);
+    for &rows in &rows {
+        for clients in [10, 1_000, AccountID::MAX] {
+            let path = input_file(rows, clients);
+            group.throughput(Throughput::Elements(rows));
+            group.bench_with_input(
+                BenchmarkId::new(format!("{}_rows", rows), format!("{}_clients", clients)),
+                &path,
+                |b, path| b.to_async(&runtime).iter(|| run_pipeline(path.clone())),
+            );
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_parse, bench_account, bench_pipeline);
+criterion_main!(benches);
@@ -1,14 +1,21 @@
-use std::collections::HashMap;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
 
-use serde::Serialize;
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
 
-use crate::transaction::{Transaction, TransactionType};
+use crate::budget::MemoryBudget;
+use crate::store::{MemoryStore, Retain, TxStore};
+use crate::transaction::{RejectionReason, Transaction, TransactionType};
 use crate::{
     primitives::{AccountID, Coin, TxID, PRECISION},
     transaction::AncestorState,
 };
 
-#[derive(Clone, Eq, PartialEq, Debug, Serialize)]
+#[derive(Clone, Debug, Serialize)]
 pub struct Account {
     #[serde(rename = "client")]
     id: AccountID,
@@ -17,9 +24,437 @@ pub struct Account {
     total: Coin,
     locked: bool,
     #[serde(skip)]
-    txs: HashMap<TxID, Vec<Transaction>>, // DB for transactions stored by TxID, Vec represents successful transactions
+    txs: Box<dyn TxStore>, // DB for transactions stored by TxID and failed transactions with the reason of failure
     #[serde(skip)]
-    failed: Vec<Transaction>, // DB for failed transactions
+    overdraft_limit: Coin, // how far below zero withdrawals may take available funds
+    #[serde(skip)]
+    audit: Vec<Transaction>, // administrative transactions applied to the account
+    #[serde(skip)]
+    dispute_policy: DisputePolicy,
+    #[serde(skip)]
+    statement: Vec<StatementEntry>, // applied transactions with balances after them
+    #[serde(skip)]
+    keep_statement: bool, // applied transactions are added to the statement
+    #[serde(skip)]
+    violation: Option<InvariantViolation>, // first transaction which broke the ledger invariants
+    #[serde(skip)]
+    events: Vec<(u64, AccountEvent)>, // balance changes with sequence number of the transaction causing them
+    #[serde(skip)]
+    checkpoint: (u64, Balance), // balance after events up to sequence number, which are folded into it
+    #[serde(skip)]
+    budget: Option<MemoryBudget>,
+    #[serde(skip)]
+    stored: usize, // transactions in lifecycles, counted from the last compaction
+    #[serde(skip)]
+    compact_at: usize, // number of stored transactions which triggers compaction
+    #[serde(skip)]
+    disputed_withdrawals: usize, // withdrawals with held part, held funds can be negative only with them
+}
+
+/// Accounts are equal if they have the same balances, configuration and history,
+/// history which can't be read makes them different
+impl PartialEq for Account {
+    fn eq(&self, other: &Self) -> bool {
+        self.check_amounts(other)
+            && self.overdraft_limit == other.overdraft_limit
+            && self.dispute_policy == other.dispute_policy
+            && self.audit == other.audit
+            && self.statement == other.statement
+            && self.keep_statement == other.keep_statement
+            && self.violation == other.violation
+            && self.events == other.events
+            && self.checkpoint == other.checkpoint
+            && same(self.txs.lifecycles(), other.txs.lifecycles())
+            && same(self.txs.expired(), other.txs.expired())
+            && same(self.txs.failed(), other.txs.failed())
+    }
+}
+
+impl Eq for Account {}
+
+fn same<T: PartialEq>(a: anyhow::Result<T>, b: anyhow::Result<T>) -> bool {
+    matches!((a, b), (Ok(a), Ok(b)) if a == b)
+}
+
+/// Reason why transaction is not applied: it is rejected, or its history can't be read or written
+enum ApplyError {
+    Rejected(RejectionReason),
+    Store(anyhow::Error),
+}
+
+impl From<RejectionReason> for ApplyError {
+    fn from(reason: RejectionReason) -> Self {
+        ApplyError::Rejected(reason)
+    }
+}
+
+impl From<anyhow::Error> for ApplyError {
+    fn from(err: anyhow::Error) -> Self {
+        ApplyError::Store(err)
+    }
+}
+
+/// Change of the account balances, amounts are negative for withdrawal and its disputes
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Serialize, Deserialize)]
+pub enum AccountEvent {
+    Credited(Coin),    // added to available and total by deposit or withdrawal
+    Held(Coin),        // moved from available to held by dispute
+    Released(Coin),    // moved from held to available by resolve
+    ChargedBack(Coin), // removed from held and total by chargeback
+    Locked,            // by chargeback, freeze or close
+    Unlocked,
+}
+
+/// Balances of the account at some point of its history
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Serialize, Deserialize)]
+pub struct Balance {
+    pub available: Coin,
+    pub held: Coin,
+    pub total: Coin,
+    pub locked: bool,
+}
+
+impl Default for Balance {
+    fn default() -> Self {
+        Self {
+            available: Coin::new(0, PRECISION),
+            held: Coin::new(0, PRECISION),
+            total: Coin::new(0, PRECISION),
+            locked: false,
+        }
+    }
+}
+
+impl Balance {
+    fn apply(&mut self, event: AccountEvent) {
+        match event {
+            AccountEvent::Credited(amount) => {
+                self.available += amount;
+                self.total += amount;
+            }
+            AccountEvent::Held(amount) => {
+                self.available -= amount;
+                self.held += amount;
+            }
+            AccountEvent::Released(amount) => {
+                self.available += amount;
+                self.held -= amount;
+            }
+            AccountEvent::ChargedBack(amount) => {
+                self.held -= amount;
+                self.total -= amount;
+            }
+            AccountEvent::Locked => self.locked = true,
+            AccountEvent::Unlocked => self.locked = false,
+        }
+    }
+}
+
+/// Ledger rule which must hold after every transaction
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum Invariant {
+    TotalMismatch,         // available + held == total
+    NegativeHeld,          // held >= 0 while no withdrawal is disputed
+    NegativeDisputed,      // disputed and charged back parts of transaction are not negative
+    DisputedExceedsAmount, // disputed and charged back parts don't exceed transaction amount
+}
+
+impl fmt::Display for Invariant {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let text = match self {
+            Invariant::TotalMismatch => "available + held != total",
+            Invariant::NegativeHeld => "held is negative while no withdrawal is disputed",
+            Invariant::NegativeDisputed => "disputed or charged back part is negative",
+            Invariant::DisputedExceedsAmount => {
+                "disputed and charged back parts exceed transaction amount"
+            }
+        };
+        f.write_str(text)
+    }
+}
+
+/// Transaction after which the account broke an invariant, with account state before and after it
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct InvariantViolation {
+    pub invariant: Invariant,
+    pub client: AccountID,
+    pub tx: Transaction,
+    pub before: (Coin, Coin, Coin), // available, held, total
+    pub after: (Coin, Coin, Coin),  // available, held, total
+}
+
+impl fmt::Display for InvariantViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "client {}: {}: available/held/total {}/{}/{} before, {}/{}/{} after {:?}",
+            self.client,
+            self.invariant,
+            self.before.0,
+            self.before.1,
+            self.before.2,
+            self.after.0,
+            self.after.1,
+            self.after.2,
+            self.tx,
+        )
+    }
+}
+
+/// Transaction applied to the account with the account state after it
+#[derive(Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+pub struct StatementEntry {
+    pub tx: Transaction,
+    pub amount: Option<Coin>, // funds moved by the transaction, none for administrative transactions
+    pub available: Coin,
+    pub held: Coin,
+    pub total: Coin,
+    pub locked: bool,
+}
+
+/// State of the account without its configuration, for snapshots
+#[derive(Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+pub struct AccountState {
+    id: AccountID,
+    available: Coin,
+    held: Coin,
+    total: Coin,
+    locked: bool,
+    txs: HashMap<TxID, TxLifecycle>,
+    failed: Vec<(Transaction, RejectionReason)>,
+    audit: Vec<Transaction>,
+    statement: Vec<StatementEntry>,
+    #[serde(default)] // not saved by older snapshots
+    events: Vec<(u64, AccountEvent)>,
+    #[serde(default)]
+    expired: HashSet<TxID>,
+    #[serde(default)]
+    checkpoint: (u64, Balance),
+}
+
+impl AccountState {
+    pub fn id(&self) -> AccountID {
+        self.id
+    }
+}
+
+impl From<AccountState> for Account {
+    fn from(state: AccountState) -> Self {
+        let disputed_withdrawals = state
+            .txs
+            .values()
+            .filter(|lifecycle| lifecycle.is_disputed_withdrawal())
+            .count();
+        let txs = MemoryStore::new(state.txs, state.expired, state.failed);
+        Self {
+            available: state.available,
+            held: state.held,
+            total: state.total,
+            locked: state.locked,
+            txs: Box::new(txs),
+            audit: state.audit,
+            statement: state.statement,
+            events: state.events,
+            checkpoint: state.checkpoint,
+            disputed_withdrawals,
+            ..Account::new(state.id)
+        }
+    }
+}
+
+/// Limits for disputes of deposits and withdrawals
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Default)]
+pub struct DisputePolicy {
+    pub window: Option<DisputeWindow>, // how long after deposit or withdrawal it can be disputed
+    pub max_disputes: Option<usize>,   // disputes allowed per transaction, partial ones included
+}
+
+/// Time after deposit or withdrawal in which it can be disputed
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum DisputeWindow {
+    Seconds(u64),      // by timestamps, dispute is rejected if any of them is missing
+    Transactions(u64), // by sequence numbers of transactions in the input
+}
+
+impl DisputeWindow {
+    /// check if `dispute` comes within the window after `parent`, none if it can't be told without timestamps
+    fn contains(&self, parent: &TxRecord, dispute: &Transaction) -> Option<bool> {
+        match self {
+            DisputeWindow::Seconds(window) => match (parent.timestamp, dispute.timestamp()) {
+                (Some(parent), Some(dispute)) => Some(dispute.saturating_sub(parent) <= *window),
+                _ => None,
+            },
+            DisputeWindow::Transactions(window) => {
+                Some(dispute.seq().saturating_sub(parent.seq) <= *window)
+            }
+        }
+    }
+}
+
+/// Successful transactions with the same TxID: deposit or withdrawal and disputes, resolves and chargebacks of it
+#[derive(Clone, Eq, PartialEq, Debug, Serialize, Deserialize)]
+pub struct TxLifecycle {
+    txs: Vec<Transaction>, // first transaction is deposit or withdrawal, unless it is compacted
+    held: Coin,            // part of the amount which is under dispute now
+    charged_back: Coin,    // part of the amount which is charged back
+    #[serde(default)]
+    evicted_disputes: usize, // disputes removed from `txs` by compaction
+    #[serde(default)]
+    compacted: Option<TxRecord>, // deposit or withdrawal removed from `txs` by compaction
+}
+
+/// Deposit or withdrawal reduced to what later transactions of its lifecycle are checked against,
+/// its id and account are the key of the lifecycle
+#[derive(Clone, Copy, Eq, PartialEq, Debug, Serialize, Deserialize)]
+pub struct TxRecord {
+    tx_type: TransactionType,
+    amount: Coin,
+    seq: u64,               // for dispute window in transactions
+    timestamp: Option<u64>, // for dispute window in seconds
+}
+
+impl From<&Transaction> for TxRecord {
+    fn from(tx: &Transaction) -> Self {
+        Self {
+            tx_type: tx.tx_type(),
+            amount: tx.amount(),
+            seq: tx.seq(),
+            timestamp: tx.timestamp(),
+        }
+    }
+}
+
+impl TxLifecycle {
+    fn new(parent: Transaction) -> Self {
+        Self {
+            txs: vec![parent],
+            held: Coin::new(0, PRECISION),
+            charged_back: Coin::new(0, PRECISION),
+            evicted_disputes: 0,
+            compacted: None,
+        }
+    }
+
+    fn parent(&self) -> TxRecord {
+        // lifecycle is created with parent, so it is the first transaction until it is compacted
+        self.compacted
+            .unwrap_or_else(|| TxRecord::from(&self.txs[0]))
+    }
+
+    /// Number of transactions kept in the lifecycle, compacted deposit or withdrawal included
+    fn len(&self) -> usize {
+        self.txs.len() + usize::from(self.compacted.is_some())
+    }
+
+    /// Check transaction against the last transaction of the lifecycle
+    fn valid_ancestor(&self, tx: &Transaction) -> AncestorState {
+        match self.txs.last() {
+            Some(last) => tx.valid_ancestor(last),
+            // compaction keeps nothing after deposit or withdrawal, see `compact`
+            None => tx.valid_ancestor_type(self.parent().tx_type),
+        }
+    }
+
+    fn is_disputed_withdrawal(&self) -> bool {
+        self.parent().tx_type == TransactionType::Withdrawal && self.held != Coin::new(0, 0)
+    }
+
+    /// part of the parent amount which can be disputed
+    fn undisputed(&self) -> Coin {
+        self.parent().amount - self.held - self.charged_back
+    }
+
+    /// Check that dispute is allowed by the policy: it is within the window after parent transaction
+    /// and parent transaction is not disputed too many times
+    fn check_policy(
+        &self,
+        tx: &Transaction,
+        policy: &DisputePolicy,
+    ) -> Result<(), RejectionReason> {
+        if tx.tx_type() != TransactionType::Dispute {
+            return Ok(());
+        }
+        if let Some(window) = policy.window {
+            match window.contains(&self.parent(), tx) {
+                Some(true) => {}
+                Some(false) => return Err(RejectionReason::DisputeWindowExpired),
+                None => return Err(RejectionReason::MissingTimestamp),
+            }
+        }
+        if let Some(max_disputes) = policy.max_disputes {
+            if self.disputes() >= max_disputes {
+                return Err(RejectionReason::TooManyDisputes);
+            }
+        }
+        Ok(())
+    }
+
+    fn disputes(&self) -> usize {
+        let kept = self
+            .txs
+            .iter()
+            .filter(|tx| tx.tx_type() == TransactionType::Dispute)
+            .count();
+        kept + self.evicted_disputes
+    }
+
+    /// Keep only record of parent transaction and return number of removed transactions,
+    /// none if there is nothing to compact
+    ///
+    /// it doesn't change results of later transactions if nothing is held:
+    /// resolve and chargeback are rejected as not disputed whatever transaction is the last one
+    fn compact(&mut self) -> Option<usize> {
+        if self.compacted.is_some() && self.txs.is_empty() {
+            return None;
+        }
+        self.evicted_disputes = self.disputes();
+        let removed = self.len() - 1;
+        self.compacted = Some(self.parent());
+        self.txs = Vec::new(); // frees the memory, unlike `clear`
+        Some(removed)
+    }
+
+    /// Amount of dispute, resolve or chargeback:
+    ///
+    /// amount given in transaction or the whole undisputed part for dispute, the whole held part for resolve and chargeback
+    ///
+    /// it can't exceed undisputed part for dispute and held part for resolve and chargeback
+    fn amount_for(&self, tx: &Transaction) -> Result<Coin, RejectionReason> {
+        let (limit, nothing_left, exceeded) = match tx.tx_type() {
+            TransactionType::Dispute => (
+                self.undisputed(),
+                RejectionReason::AlreadyDisputed,
+                RejectionReason::DisputeExceedsUndisputed,
+            ),
+            _ => (
+                self.held,
+                RejectionReason::NotDisputed,
+                RejectionReason::ExceedsDisputed,
+            ),
+        };
+        if limit <= Coin::new(0, 0) {
+            return Err(nothing_left);
+        }
+        let amount = tx.input_amount().unwrap_or(limit);
+        if amount > limit {
+            return Err(exceeded);
+        }
+        Ok(amount)
+    }
+
+    /// add transaction to lifecycle and move `amount` between undisputed, held and charged back parts
+    fn push(&mut self, tx: Transaction, amount: Coin) {
+        match tx.tx_type() {
+            TransactionType::Dispute => self.held += amount,
+            TransactionType::Resolve => self.held -= amount,
+            TransactionType::Chargeback => {
+                self.held -= amount;
+                self.charged_back += amount;
+            }
+            _ => {}
+        }
+        self.txs.push(tx);
+    }
 }
 
 impl Account {
@@ -30,8 +465,19 @@ impl Account {
             held: Coin::new(0, PRECISION),
             total: Coin::new(0, PRECISION),
             locked: false,
-            txs: HashMap::new(),
-            failed: Vec::new(),
+            txs: Box::new(MemoryStore::default()),
+            overdraft_limit: Coin::new(0, PRECISION),
+            audit: Vec::new(),
+            dispute_policy: DisputePolicy::default(),
+            statement: Vec::new(),
+            keep_statement: false,
+            violation: None,
+            events: Vec::new(),
+            checkpoint: (0, Balance::default()),
+            budget: None,
+            stored: 0,
+            compact_at: 0,
+            disputed_withdrawals: 0,
         }
     }
 
@@ -55,6 +501,51 @@ impl Account {
         Self { locked, ..self }
     }
 
+    pub fn set_overdraft_limit(self, overdraft_limit: Coin) -> Self {
+        Self {
+            overdraft_limit,
+            ..self
+        }
+    }
+
+    pub fn set_dispute_policy(self, dispute_policy: DisputePolicy) -> Self {
+        Self {
+            dispute_policy,
+            ..self
+        }
+    }
+
+    /// Add every applied transaction to the statement, it is not kept by default as it grows with every transaction
+    pub fn set_statement(self, keep_statement: bool) -> Self {
+        Self {
+            keep_statement,
+            ..self
+        }
+    }
+
+    /// Compact transaction history and stream failed transactions out to keep memory within `budget`
+    pub fn set_memory_budget(self, budget: MemoryBudget) -> Self {
+        Self {
+            compact_at: budget.max_txs,
+            budget: Some(budget),
+            ..self
+        }
+    }
+
+    /// Move transaction history of the account to `txs`, fails if history can't be read or written
+    pub fn set_tx_store(self, mut txs: Box<dyn TxStore>) -> anyhow::Result<Self> {
+        for (id, lifecycle) in self.txs.lifecycles()? {
+            txs.put(id, lifecycle)?;
+        }
+        for id in self.txs.expired()? {
+            txs.expire(id)?;
+        }
+        for (tx, reason) in self.txs.failed()?.into_owned() {
+            txs.record_failure(tx, reason)?;
+        }
+        Ok(Self { txs, ..self })
+    }
+
     #[allow(dead_code)]
     pub fn check_amounts(&self, other: &Self) -> bool {
         self.id == other.id
@@ -74,51 +565,342 @@ impl Account {
     ///
     /// if latest transaction on account is not valid ancestor -> insert failed
     ///
-    /// if there are no previous transactions with this id -> insert valid    
-    pub async fn process(&mut self, tx: &Transaction) {
-        let mut success = false;
-
-        if !self.is_locked() {
-            match self.txs.get_mut(&tx.id()) {
-                Some(previous_txs) => {
-                    // if there are previous transactions
-                    // we need to check latest transaction on account to see if it is valid ancestor
-                    if let Some(last) = previous_txs.last() {
-                        if tx.valid_ancestor(last) == AncestorState::Valid {
-                            // first transaction should be deposit or withdrawal and we can take amount from it
-                            let first = previous_txs[0].clone(); //XXX: as we checked last(), there should be first
-                            previous_txs.push(tx.clone());
-                            self.calc_transaction(first.amount(), &tx.tx_type(), &first.tx_type());
-                            success = true;
-                        }
+    /// if withdrawal would overdraw available funds beyond overdraft limit -> insert failed
+    ///
+    /// failed transactions are stored together with the reason of failure
+    ///
+    /// in debug builds or with `invariants` feature ledger invariants are checked after every applied transaction
+    ///
+    /// fails only if transaction history can't be read or written
+    pub async fn process(&mut self, tx: &Transaction) -> anyhow::Result<()> {
+        self.try_process(tx).map(|_| ()) // failed transaction is stored in the account
+    }
+
+    /// Same as `process`, but also returns the reason if transaction failed
+    pub fn try_process(&mut self, tx: &Transaction) -> anyhow::Result<Result<(), RejectionReason>> {
+        let before = (self.available, self.held, self.total);
+        let res = match self.apply(tx) {
+            Ok(amount) => {
+                // statement grows with every applied transaction, so it is not kept with memory budget
+                if self.keep_statement && self.budget.is_none() {
+                    self.statement.push(StatementEntry {
+                        tx: tx.clone(),
+                        amount,
+                        available: self.available,
+                        held: self.held,
+                        total: self.total,
+                        locked: self.locked,
+                    });
+                }
+                self.compact(tx)?;
+                Ok(())
+            }
+            Err(ApplyError::Rejected(reason)) => {
+                self.reject(tx, reason)?;
+                Err(reason)
+            }
+            Err(ApplyError::Store(err)) => return Err(err),
+        };
+        if cfg!(any(debug_assertions, feature = "invariants")) && self.violation.is_none() {
+            if let Err(invariant) = self.check_invariants(tx)? {
+                self.violation = Some(InvariantViolation {
+                    invariant,
+                    client: self.id,
+                    tx: tx.clone(),
+                    before,
+                    after: (self.available, self.held, self.total),
+                });
+            }
+        }
+        Ok(res)
+    }
+
+    /// Check invariants of the account and of the lifecycle of `tx`
+    ///
+    /// held funds of the account are negative while a withdrawal is disputed,
+    /// so they are checked only without disputed withdrawals, and disputed parts of transaction are checked instead
+    fn check_invariants(&self, tx: &Transaction) -> anyhow::Result<Result<(), Invariant>> {
+        if self.available + self.held != self.total {
+            return Ok(Err(Invariant::TotalMismatch));
+        }
+        if self.disputed_withdrawals == 0 && self.held < Coin::new(0, 0) {
+            return Ok(Err(Invariant::NegativeHeld));
+        }
+        if let Some(lifecycle) = self.txs.get(tx.id())? {
+            let zero = Coin::new(0, 0);
+            if lifecycle.held < zero || lifecycle.charged_back < zero {
+                return Ok(Err(Invariant::NegativeDisputed));
+            }
+            if lifecycle.held + lifecycle.charged_back > lifecycle.parent().amount {
+                return Ok(Err(Invariant::DisputedExceedsAmount));
+            }
+        }
+        Ok(Ok(()))
+    }
+
+    /// Store transaction rejected outside of the account as failed
+    ///
+    /// with memory budget it is written to the rejected report instead, or only counted
+    pub fn reject(&mut self, tx: &Transaction, reason: RejectionReason) -> anyhow::Result<()> {
+        match &self.budget {
+            Some(budget) => {
+                budget
+                    .metrics
+                    .failed_evicted
+                    .fetch_add(1, Ordering::Relaxed);
+                if let Some(rejected) = &budget.rejected {
+                    rejected
+                        .write(&(tx.clone(), reason))
+                        .context("failed to write rejected report")?;
+                }
+                Ok(())
+            }
+            None => self.txs.record_failure(tx.clone(), reason),
+        }
+    }
+
+    /// Compact transaction history if it holds more transactions than memory budget allows
+    ///
+    /// lifecycles under dispute are kept whole, others keep only deposit or withdrawal,
+    /// or only id if dispute window in transactions is over after `tx`;
+    /// if history is still over the budget, next compaction is done when it doubles;
+    /// event log and audit trail over the budget are folded up to `tx` and cut to the last transaction
+    fn compact(&mut self, tx: &Transaction) -> anyhow::Result<()> {
+        let Some(budget) = &self.budget else {
+            return Ok(());
+        };
+        let (max_txs, metrics) = (budget.max_txs, Arc::clone(&budget.metrics));
+        if self.events.len() > max_txs {
+            let folded = self.events.len() as u64;
+            self.compact_events(tx.seq());
+            metrics.events_folded.fetch_add(folded, Ordering::Relaxed);
+        }
+        if self.audit.len() > max_txs {
+            // only the last administrative transaction is checked by the next one
+            let evicted = self.audit.drain(..self.audit.len() - 1).len() as u64;
+            metrics.audit_evicted.fetch_add(evicted, Ordering::Relaxed);
+        }
+        if self.stored <= self.compact_at {
+            return Ok(());
+        }
+        // timestamps are not ordered, so window in seconds can't expire lifecycles
+        let window = match self.dispute_policy.window {
+            Some(window @ DisputeWindow::Transactions(_)) => Some(window),
+            _ => None,
+        };
+        let (mut stored, mut compacted, mut expired, mut evicted) = (0, 0, 0, 0);
+        self.txs.retain(&mut |_, lifecycle| {
+            if lifecycle.held != Coin::new(0, 0) {
+                stored += lifecycle.len();
+                Retain::Unchanged
+            } else if window
+                .is_some_and(|window| window.contains(&lifecycle.parent(), tx) == Some(false))
+            {
+                expired += 1;
+                evicted += lifecycle.len();
+                Retain::Expired
+            } else {
+                stored += 1;
+                match lifecycle.compact() {
+                    Some(removed) => {
+                        evicted += removed;
+                        compacted += 1;
+                        Retain::Changed
                     }
+                    None => Retain::Unchanged,
                 }
-                None => {
-                    // if there is no previous transactions with this id -> insert valid
-                    if tx.tx_type() == TransactionType::Deposit
-                        || tx.tx_type() == TransactionType::Withdrawal
-                    {
-                        self.txs.insert(tx.id(), vec![tx.clone()]); // provides guarantee that first transaction is deposit or withdrawal
-                        self.calc_transaction(tx.amount(), &tx.tx_type(), &tx.tx_type());
-                        success = true;
+            }
+        })?;
+        self.stored = stored;
+        self.compact_at = max_txs.max(stored * 2);
+
+        metrics.compactions.fetch_add(1, Ordering::Relaxed);
+        metrics.compacted.fetch_add(compacted, Ordering::Relaxed);
+        metrics.expired.fetch_add(expired, Ordering::Relaxed);
+        metrics
+            .txs_evicted
+            .fetch_add(evicted as u64, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Apply transaction to the account and return funds moved by it,
+    /// or return the reason why it can't be applied
+    fn apply(&mut self, tx: &Transaction) -> Result<Option<Coin>, ApplyError> {
+        if tx.tx_type().is_admin() {
+            return Ok(self.apply_admin(tx).map(|()| None)?);
+        }
+        if self.is_closed() {
+            return Err(RejectionReason::AccountClosed.into());
+        }
+        if self.is_locked() {
+            return Err(RejectionReason::AccountLocked.into());
+        }
+
+        if self.txs.is_expired(tx.id())? {
+            // results of transactions with id of lifecycle which is not disputed after dispute window
+            return Err(ApplyError::Rejected(match tx.tx_type() {
+                TransactionType::Dispute => RejectionReason::DisputeWindowExpired,
+                TransactionType::Resolve | TransactionType::Chargeback => {
+                    RejectionReason::NotDisputed
+                }
+                _ => RejectionReason::DuplicateTransaction,
+            }));
+        }
+        let amount = match self.txs.get(tx.id())? {
+            Some(lifecycle) => {
+                // if there are previous transactions
+                // we need to check latest transaction on account to see if it is valid ancestor
+                if let AncestorState::Invalid(reason) = lifecycle.valid_ancestor(tx) {
+                    return Err(reason.into());
+                }
+                // and check that amount is covered by parent deposit or withdrawal
+                lifecycle.check_policy(tx, &self.dispute_policy)?;
+                let amount = lifecycle.amount_for(tx)?;
+                let parent_type = lifecycle.parent().tx_type;
+                let was_disputed = lifecycle.is_disputed_withdrawal();
+                let mut is_disputed = was_disputed;
+                self.txs.update(tx.id(), &mut |lifecycle| {
+                    lifecycle.push(tx.clone(), amount);
+                    is_disputed = lifecycle.is_disputed_withdrawal();
+                })?;
+                match (was_disputed, is_disputed) {
+                    (false, true) => self.disputed_withdrawals += 1,
+                    (true, false) => self.disputed_withdrawals -= 1,
+                    _ => {}
+                }
+                self.calc_transaction(amount, tx, &parent_type);
+                amount
+            }
+            None => {
+                // if there is no previous transactions with this id -> insert valid
+                match tx.tx_type() {
+                    TransactionType::Deposit | TransactionType::Withdrawal => {
+                        if tx.tx_type() == TransactionType::Withdrawal
+                            && !self.can_withdraw(tx.amount())
+                        {
+                            return Err(RejectionReason::InsufficientFunds.into());
+                        }
+                        self.txs.put(tx.id(), TxLifecycle::new(tx.clone()))?; // provides guarantee that first transaction is deposit or withdrawal
+                        self.calc_transaction(tx.amount(), tx, &tx.tx_type());
+                        tx.amount()
                     }
+                    _ => return Err(RejectionReason::MissingParent.into()),
                 }
-            };
+            }
+        };
+        self.stored += 1;
+        Ok(Some(amount))
+    }
+
+    /// Apply administrative transaction, it is not affected by the account lock
+    fn apply_admin(&mut self, tx: &Transaction) -> Result<(), RejectionReason> {
+        if let AncestorState::Invalid(reason) =
+            tx.valid_admin_ancestor(self.audit.last(), self.is_locked())
+        {
+            return Err(reason);
+        }
+        self.calc_transaction(Coin::new(0, PRECISION), tx, &tx.tx_type());
+        self.audit.push(tx.clone());
+        Ok(())
+    }
+
+    pub fn id(&self) -> AccountID {
+        self.id
+    }
+
+    pub fn available(&self) -> Coin {
+        self.available
+    }
+
+    pub fn held(&self) -> Coin {
+        self.held
+    }
+
+    pub fn total(&self) -> Coin {
+        self.total
+    }
+
+    /// Get state of the account for snapshot, configuration is not included
+    ///
+    /// fails if transaction history can't be read
+    pub fn state(&self) -> anyhow::Result<AccountState> {
+        Ok(AccountState {
+            id: self.id,
+            available: self.available,
+            held: self.held,
+            total: self.total,
+            locked: self.locked,
+            txs: self.txs.lifecycles()?,
+            expired: self.txs.expired()?,
+            failed: self.txs.failed()?.into_owned(),
+            audit: self.audit.clone(),
+            statement: self.statement.clone(),
+            events: self.events.clone(),
+            checkpoint: self.checkpoint,
+        })
+    }
+
+    /// Get administrative transactions applied to the account, in order of application
+    pub fn audit_trail(&self) -> &[Transaction] {
+        &self.audit
+    }
+
+    /// Get transactions applied to the account in order of application, with the account state after each of them
+    pub fn statement(&self) -> &[StatementEntry] {
+        &self.statement
+    }
+
+    /// Get the first transaction which broke ledger invariants, if they are checked
+    pub fn invariant_violation(&self) -> Option<&InvariantViolation> {
+        self.violation.as_ref()
+    }
+
+    /// Get balance changes in order of application, with sequence number of the transaction causing them
+    pub fn events(&self) -> &[(u64, AccountEvent)] {
+        &self.events
+    }
+
+    /// Rebuild balances of the account after all transactions with sequence number up to `seq`
+    ///
+    /// none if events up to a later sequence number are folded by `compact_events`
+    pub fn balance_at(&self, seq: u64) -> Option<Balance> {
+        let (folded, mut balance) = self.checkpoint;
+        if seq < folded {
+            return None;
+        }
+        for &(_, event) in self.events.iter().filter(|(at, _)| *at <= seq) {
+            balance.apply(event);
         }
+        Some(balance)
+    }
 
-        if !success {
-            self.failed.push(tx.clone());
+    /// Fold events of transactions with sequence number up to `seq` into a single balance,
+    /// so the event log doesn't grow without bound; balances before `seq` can't be rebuilt after it
+    pub fn compact_events(&mut self, seq: u64) {
+        // events are logged in order of sequence numbers
+        let folded = self.events.partition_point(|(at, _)| *at <= seq);
+        for (_, event) in self.events.drain(..folded) {
+            self.checkpoint.1.apply(event);
         }
+        self.checkpoint.0 = self.checkpoint.0.max(seq);
+    }
+
+    /// Get transactions which were not applied to the account, with the reason of failure
+    ///
+    /// borrowed if the store keeps them in memory, fails if they can't be read
+    pub fn failed(&self) -> anyhow::Result<Cow<'_, [(Transaction, RejectionReason)]>> {
+        self.txs.failed()
     }
 
-    /// calculate account state after transaction
+    /// check if withdrawal of `amount` keeps available funds within overdraft limit
+    fn can_withdraw(&self, amount: Coin) -> bool {
+        self.available - amount >= -self.overdraft_limit
+    }
+
+    /// calculate account state after transaction and log the changes as events
     ///
-    fn calc_transaction(
-        &mut self,
-        amount: Coin,
-        tx_type: &TransactionType,
-        parent_type: &TransactionType,
-    ) {
+    /// `amount` is the amount of deposit or withdrawal, or the disputed part of it
+    fn calc_transaction(&mut self, amount: Coin, tx: &Transaction, parent_type: &TransactionType) {
         // we take strait amount from parent transaction if it is deposit
         // we take negative amount from parent transaction if it is withdrawal
         // then dispute, resolve and chargeback will perform correctly with this amount
@@ -128,12 +910,36 @@ impl Account {
             _ => Coin::new(0, PRECISION),
         };
 
-        match tx_type {
-            TransactionType::Deposit | TransactionType::Withdrawal => self.deposit(amount),
-            TransactionType::Dispute => self.dispute(amount),
-            TransactionType::Resolve => self.resolve(amount),
-            TransactionType::Chargeback => self.chargeback(amount),
-        }
+        let event = match tx.tx_type() {
+            TransactionType::Deposit | TransactionType::Withdrawal => {
+                self.deposit(amount);
+                AccountEvent::Credited(amount)
+            }
+            TransactionType::Dispute => {
+                self.dispute(amount);
+                AccountEvent::Held(amount)
+            }
+            TransactionType::Resolve => {
+                self.resolve(amount);
+                AccountEvent::Released(amount)
+            }
+            TransactionType::Chargeback => {
+                self.chargeback(amount);
+                self.events
+                    .push((tx.seq(), AccountEvent::ChargedBack(amount)));
+                AccountEvent::Locked
+            }
+            // administrative transactions don't move funds
+            TransactionType::Unlock => {
+                self.locked = false;
+                AccountEvent::Unlocked
+            }
+            TransactionType::Freeze | TransactionType::Close => {
+                self.locked = true;
+                AccountEvent::Locked
+            }
+        };
+        self.events.push((tx.seq(), event));
     }
 
     /// deposit Coins onto account
@@ -164,15 +970,24 @@ impl Account {
     }
 
     /// check if account is locked
-    fn is_locked(&self) -> bool {
+    pub fn is_locked(&self) -> bool {
         self.locked
     }
+
+    /// check if account is closed by administrative transaction
+    fn is_closed(&self) -> bool {
+        self.audit
+            .last()
+            .is_some_and(|tx| tx.tx_type() == TransactionType::Close)
+    }
 }
 
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison)]
 mod tests {
 
     use super::*;
+    use crate::transaction::InputTransaction;
 
     #[test]
     fn test_deposit() {
@@ -214,6 +1029,130 @@ mod tests {
         assert_eq!(account.locked, false);
     }
 
+    #[cfg(any(debug_assertions, feature = "invariants"))]
+    #[tokio::test]
+    async fn test_invariant_violation() {
+        // inconsistent state can only be built with setters
+        let mut account = Account::new(1).set_available(Coin::new(1, 0));
+        let tx = withdrawal(1, Coin::new(5, 1));
+        account.process(&tx).await.unwrap();
+        account
+            .process(&withdrawal(2, Coin::new(1, 1)))
+            .await
+            .unwrap();
+
+        let violation = account.invariant_violation().unwrap();
+        assert_eq!(violation.invariant, Invariant::TotalMismatch);
+        assert_eq!(violation.tx, tx); // first violating transaction is kept
+        assert_eq!(
+            violation.after,
+            (Coin::new(5, 1), Coin::new(0, 0), -Coin::new(5, 1))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_invariants_hold_for_disputed_withdrawal() {
+        let mut account = Account::new(1).set_overdraft_limit(Coin::new(10, 0));
+        account
+            .process(&withdrawal(1, Coin::new(5, 1)))
+            .await
+            .unwrap();
+        let dispute = Transaction::try_from(InputTransaction {
+            tx_type: "dispute".to_owned(),
+            client: "1".to_owned(),
+            id: "1".to_owned(),
+            amount: None,
+            timestamp: None,
+        })
+        .unwrap();
+        account.process(&dispute).await.unwrap();
+
+        assert_eq!(account.held, -Coin::new(5, 1));
+        assert_eq!(account.invariant_violation(), None);
+    }
+
+    #[cfg(any(debug_assertions, feature = "invariants"))]
+    #[tokio::test]
+    async fn test_invariant_negative_held() {
+        // held funds can only be negative without disputed withdrawal if they are set
+        let mut account = Account::new(1)
+            .set_held(-Coin::new(1, 0))
+            .set_total(-Coin::new(1, 0))
+            .set_overdraft_limit(Coin::new(10, 0));
+        account
+            .process(&withdrawal(1, Coin::new(5, 1)))
+            .await
+            .unwrap();
+
+        let violation = account.invariant_violation().unwrap();
+        assert_eq!(violation.invariant, Invariant::NegativeHeld);
+    }
+
+    #[tokio::test]
+    async fn test_withdrawal_insufficient_funds() {
+        let mut account = Account::new(1).set_available(Coin::new(1, 0));
+        let tx = withdrawal(1, Coin::new(11, 1));
+        account.process(&tx).await.unwrap();
+        assert_eq!(account.available, Coin::new(1, 0));
+        assert_eq!(
+            account.failed().unwrap(),
+            vec![(tx, RejectionReason::InsufficientFunds)]
+        );
+        assert!(account.txs.lifecycles().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_withdrawal_within_overdraft_limit() {
+        let mut account = Account::new(1)
+            .set_available(Coin::new(1, 0))
+            .set_overdraft_limit(Coin::new(1, 1));
+        account
+            .process(&withdrawal(1, Coin::new(11, 1)))
+            .await
+            .unwrap();
+        assert_eq!(account.available, -Coin::new(1, 1));
+        assert!(account.failed().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_compacted_lifecycle_keeps_record() {
+        let mut lifecycle = TxLifecycle::new(withdrawal(1, Coin::new(50, 1)));
+        let dispute = Transaction::try_from(InputTransaction {
+            tx_type: "dispute".to_owned(),
+            client: "1".to_owned(),
+            id: "1".to_owned(),
+            amount: Some("2.0".to_owned()),
+            timestamp: None,
+        })
+        .unwrap();
+        let amount = lifecycle.amount_for(&dispute).unwrap();
+        lifecycle.push(dispute.clone(), amount);
+        lifecycle.held = Coin::new(0, 0); // resolved
+
+        assert_eq!(lifecycle.compact(), Some(1));
+        assert!(lifecycle.txs.is_empty());
+        assert_eq!(lifecycle.compact(), None);
+        assert_eq!(lifecycle.parent().tx_type, TransactionType::Withdrawal);
+        assert_eq!(lifecycle.disputes(), 1);
+
+        // dispute after compaction is checked against the record
+        assert_eq!(lifecycle.valid_ancestor(&dispute), AncestorState::Valid);
+        assert_eq!(lifecycle.amount_for(&dispute), Ok(Coin::new(2, 0)));
+        lifecycle.push(dispute, Coin::new(2, 0));
+        assert_eq!(lifecycle.len(), 2);
+    }
+
+    fn withdrawal(id: TxID, amount: Coin) -> Transaction {
+        Transaction::try_from(InputTransaction {
+            tx_type: "withdrawal".to_owned(),
+            client: "1".to_owned(),
+            id: id.to_string(),
+            amount: Some(amount.to_string()),
+            timestamp: None,
+        })
+        .unwrap()
+    }
+
     #[test]
     fn test_chargeback() {
         let mut account = Account::new(1);
@@ -0,0 +1,70 @@
+use clap::Parser;
+use krct_async::generator::{Generator, GeneratorConfig};
+use krct_async::primitives::AccountID;
+use std::io;
+
+#[derive(Parser)]
+#[command(about = "Generate synthetic transactions CSV to stdout")]
+struct Args {
+    /// Number of clients, up to 65535
+    #[arg(long, default_value_t = 100, value_parser = clap::value_parser!(u16).range(1..))]
+    clients: AccountID,
+
+    /// Number of rows
+    #[arg(long, default_value_t = 1000)]
+    transactions: u64,
+
+    /// Probability of a row to be a dispute
+    #[arg(long, default_value_t = 0.05, value_parser = parse_rate)]
+    dispute_rate: f64,
+
+    /// Probability of a row to finalize an open dispute, by resolve or chargeback
+    #[arg(long, default_value_t = 0.05, value_parser = parse_rate)]
+    resolution_rate: f64,
+
+    /// Probability of a dispute to be finalized by chargeback instead of resolve
+    #[arg(long, default_value_t = 0.2, value_parser = parse_rate)]
+    chargeback_rate: f64,
+
+    /// Probability of a deposit or withdrawal to reuse an earlier id
+    #[arg(long, default_value_t = 0.01, value_parser = parse_rate)]
+    duplicate_rate: f64,
+
+    /// Probability of a row which can't be parsed
+    #[arg(long, default_value_t = 0.0, value_parser = parse_rate)]
+    malformed_rate: f64,
+
+    /// Probability of a field to be padded with spaces
+    #[arg(long, default_value_t = 0.0, value_parser = parse_rate)]
+    whitespace_rate: f64,
+
+    /// Seed of the random generator, the same seed gives the same output
+    #[arg(long, default_value_t = 0)]
+    seed: u64,
+}
+
+fn parse_rate(arg: &str) -> anyhow::Result<f64> {
+    let rate: f64 = arg.trim().parse()?;
+    if !(0.0..=1.0).contains(&rate) {
+        return Err(anyhow::anyhow!("rate should be from 0.0 to 1.0"));
+    }
+    Ok(rate)
+}
+
+fn main() -> anyhow::Result<()> {
+    let args = Args::parse();
+    let config = GeneratorConfig {
+        clients: args.clients,
+        transactions: args.transactions,
+        dispute_rate: args.dispute_rate,
+        resolution_rate: args.resolution_rate,
+        chargeback_rate: args.chargeback_rate,
+        duplicate_rate: args.duplicate_rate,
+        malformed_rate: args.malformed_rate,
+        whitespace_rate: args.whitespace_rate,
+        seed: args.seed,
+    };
+    let mut out = io::BufWriter::new(io::stdout().lock());
+    Generator::new(config)?.write_csv(&mut out)?;
+    Ok(())
+}
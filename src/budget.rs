@@ -0,0 +1,65 @@
+use crate::primitives::RejectedStream;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// Limit of transaction history kept by every account, for inputs which don't fit in memory
+///
+/// history of the account is compacted when it holds more than `max_txs` transactions:
+/// lifecycles which are not disputed now keep only type, amount, sequence number and timestamp of deposit or withdrawal,
+/// and only id after dispute window given in transactions, so results of later transactions don't change;
+/// failed transactions are written to `rejected` as they happen, or only counted, instead of being kept;
+/// statement is not kept, and event log and audit trail over `max_txs` are folded into the latest balance
+/// and the last administrative transaction, so balances can be rebuilt only after the last fold;
+/// owners of applied ids are kept by the service as bitmaps instead of a map entry per id
+#[derive(Debug, Clone)]
+pub struct MemoryBudget {
+    pub max_txs: usize,
+    pub rejected: Option<Arc<RejectedStream>>, // report of failed transactions
+    pub metrics: Arc<EvictionMetrics>,         // shared by all accounts
+}
+
+impl MemoryBudget {
+    pub fn new(max_txs: usize) -> Self {
+        Self {
+            max_txs,
+            rejected: None,
+            metrics: Arc::default(),
+        }
+    }
+
+    pub fn with_rejected(self, rejected: RejectedStream) -> Self {
+        Self {
+            rejected: Some(Arc::new(rejected)),
+            ..self
+        }
+    }
+}
+
+/// Counters of evicted history of all accounts
+#[derive(Debug, Default)]
+pub struct EvictionMetrics {
+    pub compactions: AtomicU64,    // scans of account history
+    pub compacted: AtomicU64,      // lifecycles reduced to record of deposit or withdrawal
+    pub expired: AtomicU64,        // lifecycles reduced to id after dispute window
+    pub txs_evicted: AtomicU64,    // transactions removed from lifecycles
+    pub failed_evicted: AtomicU64, // failed transactions streamed out instead of kept
+    pub events_folded: AtomicU64,  // balance changes folded into checkpoint balance
+    pub audit_evicted: AtomicU64,  // administrative transactions removed from audit trail
+}
+
+impl fmt::Display for EvictionMetrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} compactions: {} lifecycles compacted, {} expired, {} transactions, {} failed transactions and {} administrative transactions evicted, {} events folded",
+            self.compactions.load(Ordering::Relaxed),
+            self.compacted.load(Ordering::Relaxed),
+            self.expired.load(Ordering::Relaxed),
+            self.txs_evicted.load(Ordering::Relaxed),
+            self.failed_evicted.load(Ordering::Relaxed),
+            self.audit_evicted.load(Ordering::Relaxed),
+            self.events_folded.load(Ordering::Relaxed),
+        )
+    }
+}
@@ -0,0 +1,253 @@
+use crate::primitives::{AccountID, Coin, TxID, PRECISION};
+use anyhow::anyhow;
+use rand::{seq::SliceRandom, Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use std::io;
+
+/// Knobs of the synthetic transactions, rates are probabilities of a row from 0.0 to 1.0
+#[derive(Debug, Clone)]
+pub struct GeneratorConfig {
+    pub clients: AccountID,
+    pub transactions: u64,
+    pub dispute_rate: f64, // row is dispute of earlier deposit or withdrawal of the client
+    pub resolution_rate: f64, // row finalizes an open dispute by resolve or chargeback
+    pub chargeback_rate: f64, // dispute is finalized by chargeback instead of resolve
+    pub duplicate_rate: f64, // deposit or withdrawal reuses an earlier id
+    pub malformed_rate: f64, // row can't be parsed
+    pub whitespace_rate: f64, // field is padded with spaces
+    pub seed: u64,
+}
+
+impl Default for GeneratorConfig {
+    fn default() -> Self {
+        Self {
+            clients: 100,
+            transactions: 1000,
+            dispute_rate: 0.05,
+            resolution_rate: 0.05,
+            chargeback_rate: 0.2,
+            duplicate_rate: 0.01,
+            malformed_rate: 0.0,
+            whitespace_rate: 0.0,
+            seed: 0,
+        }
+    }
+}
+
+/// Generates CSV rows of transactions, the same seed gives the same rows
+pub struct Generator {
+    config: GeneratorConfig,
+    rng: ChaCha8Rng,
+    rows: u64,
+    next_id: TxID,
+    txs: Vec<Vec<TxID>>,              // deposits and withdrawals of every client
+    disputes: Vec<(AccountID, TxID)>, // disputes which are not finalized yet
+}
+
+impl Generator {
+    /// Fails if there are no clients or a rate is not within 0..=1
+    pub fn new(config: GeneratorConfig) -> anyhow::Result<Self> {
+        if config.clients == 0 {
+            return Err(anyhow!("generator needs at least one client"));
+        }
+        let rates = [
+            ("dispute", config.dispute_rate),
+            ("resolution", config.resolution_rate),
+            ("chargeback", config.chargeback_rate),
+            ("duplicate", config.duplicate_rate),
+            ("malformed", config.malformed_rate),
+            ("whitespace", config.whitespace_rate),
+        ];
+        for (name, rate) in rates {
+            if !(0.0..=1.0).contains(&rate) {
+                return Err(anyhow!(
+                    "{} rate should be within 0..=1, but got {}",
+                    name,
+                    rate
+                ));
+            }
+        }
+        Ok(Self {
+            rng: ChaCha8Rng::seed_from_u64(config.seed),
+            txs: vec![Vec::new(); usize::from(config.clients)],
+            config,
+            rows: 0,
+            next_id: 1,
+            disputes: Vec::new(),
+        })
+    }
+
+    /// Write header and all rows
+    pub fn write_csv<W: io::Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        writeln!(writer, "type,client,tx,amount")?;
+        for row in self {
+            writeln!(writer, "{}", row)?;
+        }
+        writer.flush()
+    }
+
+    fn row(&mut self) -> String {
+        if self.rng.gen_bool(self.config.malformed_rate) {
+            return self.malformed();
+        }
+        if !self.disputes.is_empty() && self.rng.gen_bool(self.config.resolution_rate) {
+            let (client, id) = self
+                .disputes
+                .swap_remove(self.rng.gen_range(0..self.disputes.len()));
+            let tx_type = if self.rng.gen_bool(self.config.chargeback_rate) {
+                "chargeback"
+            } else {
+                "resolve"
+            };
+            return self.fields(tx_type, client, id, None);
+        }
+
+        let client = self.rng.gen_range(1..=self.config.clients);
+        let txs = &self.txs[usize::from(client - 1)];
+        if !txs.is_empty() && self.rng.gen_bool(self.config.dispute_rate) {
+            let id = *txs.choose(&mut self.rng).expect("not empty");
+            self.disputes.push((client, id));
+            return self.fields("dispute", client, id, None);
+        }
+
+        let id = if self.next_id > 1 && self.rng.gen_bool(self.config.duplicate_rate) {
+            self.rng.gen_range(1..self.next_id)
+        } else {
+            self.next_id += 1;
+            self.next_id - 1
+        };
+        self.txs[usize::from(client - 1)].push(id);
+        let tx_type = if self.rng.gen_bool(0.6) {
+            "deposit"
+        } else {
+            "withdrawal"
+        };
+        let amount = Coin::new(self.rng.gen_range(1..=10_000_000), PRECISION);
+        self.fields(tx_type, client, id, Some(amount.to_string()))
+    }
+
+    /// Row which fails to parse: unknown type, bad number or negative amount
+    fn malformed(&mut self) -> String {
+        let client = self.rng.gen_range(1..=self.config.clients);
+        match self.rng.gen_range(0..4) {
+            0 => format!("transfer,{},{},1.0", client, self.next_id),
+            1 => format!("deposit,{},x{},1.0", client, self.next_id),
+            2 => format!("deposit,{},{},-1.0", client, self.next_id),
+            _ => format!("withdrawal,client{},{},1.0", client, self.next_id),
+        }
+    }
+
+    fn fields(
+        &mut self,
+        tx_type: &str,
+        client: AccountID,
+        id: TxID,
+        amount: Option<String>,
+    ) -> String {
+        let fields = [
+            tx_type.to_owned(),
+            client.to_string(),
+            id.to_string(),
+            amount.unwrap_or_default(),
+        ];
+        fields
+            .into_iter()
+            .map(|field| {
+                if self.rng.gen_bool(self.config.whitespace_rate) {
+                    format!(" {} ", field)
+                } else {
+                    field
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+}
+
+impl Iterator for Generator {
+    type Item = String;
+
+    fn next(&mut self) -> Option<String> {
+        if self.rows >= self.config.transactions {
+            return None;
+        }
+        self.rows += 1;
+        Some(self.row())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::transaction::{InputTransaction, Transaction};
+
+    fn parse(row: &str) -> anyhow::Result<Transaction> {
+        let mut rdr = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .from_reader(row.as_bytes());
+        let record = rdr.records().next().expect("one row")?;
+        let headers = csv::StringRecord::from(vec!["type", "client", "tx", "amount"]);
+        Transaction::try_from(record.deserialize::<InputTransaction>(Some(&headers))?)
+    }
+
+    #[test]
+    fn test_same_seed_same_rows() {
+        let config = GeneratorConfig {
+            malformed_rate: 0.1,
+            whitespace_rate: 0.1,
+            seed: 42,
+            ..Default::default()
+        };
+        let first = Generator::new(config.clone()).unwrap().collect::<Vec<_>>();
+        let second = Generator::new(config.clone()).unwrap().collect::<Vec<_>>();
+        let other = Generator::new(GeneratorConfig { seed: 7, ..config })
+            .unwrap()
+            .collect::<Vec<_>>();
+
+        assert_eq!(first.len(), 1000);
+        assert_eq!(first, second);
+        assert_ne!(first, other);
+    }
+
+    #[test]
+    fn test_rows_parse() {
+        let config = GeneratorConfig {
+            clients: AccountID::MAX,
+            dispute_rate: 0.3,
+            whitespace_rate: 0.5,
+            ..Default::default()
+        };
+        for row in Generator::new(config).unwrap() {
+            assert!(parse(&row).is_ok(), "{}", row);
+        }
+
+        let config = GeneratorConfig {
+            malformed_rate: 1.0,
+            ..Default::default()
+        };
+        for row in Generator::new(config).unwrap() {
+            assert!(parse(&row).is_err(), "{}", row);
+        }
+    }
+
+    #[test]
+    fn test_invalid_config() {
+        let config = GeneratorConfig {
+            clients: 0,
+            ..Default::default()
+        };
+        let err = Generator::new(config).err().unwrap();
+        assert_eq!(err.to_string(), "generator needs at least one client");
+
+        let config = GeneratorConfig {
+            dispute_rate: 1.5,
+            ..Default::default()
+        };
+        let err = Generator::new(config).err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            "dispute rate should be within 0..=1, but got 1.5"
+        );
+    }
+}
@@ -1,4 +1,9 @@
 pub mod account;
+pub mod budget;
+pub mod generator;
 pub mod primitives;
 pub mod service;
+pub mod snapshot;
+pub mod store;
 pub mod transaction;
+pub mod wal;
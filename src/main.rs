@@ -1,41 +1,330 @@
-mod account;
-mod primitives;
-mod service;
-mod transaction;
-
-use primitives::run_reader;
-use std::{env, ffi::OsString};
+use clap::Parser;
+use krct_async::account::{DisputePolicy, DisputeWindow};
+use krct_async::budget::MemoryBudget;
+use krct_async::primitives::{
+    run_readers, sort_accounts, write_rejected, write_results, write_statement, AccountID, Coin,
+    CsvLayout, Input, InputFormat, OutputFormat, ReaderConfig, RejectedStream, SortBy,
+    CHANNEL_BUUFER_SIZE,
+};
+use krct_async::service::Service;
+use krct_async::snapshot::Snapshot;
+use krct_async::store::StoreBackend;
+use krct_async::transaction::InputTransaction;
+use krct_async::wal::{Processed, Wal};
+use std::{
+    collections::HashMap,
+    ffi::OsString,
+    fs::File,
+    io::{self, Write},
+    path::PathBuf,
+};
 use tokio::sync::mpsc;
 
-use crate::primitives::{write_results, CHANNEL_BUUFER_SIZE};
-use crate::service::Service;
+#[derive(Parser)]
+#[command(
+    about = "Process transactions from CSV or JSON Lines files and print final account states"
+)]
+struct Args {
+    /// Paths or glob patterns of the files with transactions, `-` reads from stdin
+    #[arg(required = true)]
+    inputs: Vec<OsString>,
+
+    /// Format of all inputs: csv or jsonl (JSON Lines), detected by file extension if not set
+    #[arg(long, value_name = "FORMAT")]
+    input_format: Option<InputFormat>,
+
+    /// Delimiter of CSV inputs, single character or `tab`
+    #[arg(long, value_name = "CHAR", default_value = ",", value_parser = parse_delimiter)]
+    delimiter: u8,
+
+    /// CSV inputs have no header row, columns are `type,client,tx,amount,timestamp` unless mapped with `--column`
+    #[arg(long)]
+    no_headers: bool,
+
+    /// Read FIELD (type, client, tx, amount or timestamp) from CSV column COLUMN,
+    /// which is header name, or 1-based position with `--no-headers`; can be repeated
+    #[arg(long = "column", value_name = "FIELD=COLUMN", value_parser = parse_column)]
+    columns: Vec<(String, String)>,
+
+    /// Read all inputs at once instead of one after another, only correct if every client is in one input
+    #[arg(long)]
+    concurrent: bool,
+
+    /// Allow CLIENT to overdraw available funds by up to AMOUNT, can be repeated
+    #[arg(long = "overdraft-limit", value_name = "CLIENT=AMOUNT", value_parser = parse_overdraft_limit)]
+    overdraft_limits: Vec<(AccountID, Coin)>,
+
+    /// Format of the account states or statement printed to stdout: csv, json (array) or jsonl (JSON Lines)
+    #[arg(long, value_name = "FORMAT", default_value = "csv")]
+    output_format: OutputFormat,
+
+    /// Order of the account states: client (ascending id), or total, available or held (descending)
+    #[arg(long, value_name = "FIELD", default_value = "client")]
+    sort_by: SortBy,
+
+    /// Write rejected transactions with the reason of rejection to PATH (JSON if PATH ends with `.json`, CSV otherwise)
+    #[arg(long, value_name = "PATH")]
+    rejected_report: Option<PathBuf>,
+
+    /// Reject disputes which come later than the window after deposit or withdrawal:
+    /// `<N>d` days or `<N>s` seconds by `timestamp` column, `<N>tx` transactions by input order
+    #[arg(long, value_name = "WINDOW", value_parser = parse_dispute_window)]
+    dispute_window: Option<DisputeWindow>,
 
-fn get_first_arg() -> anyhow::Result<OsString> {
-    match env::args_os().nth(1) {
-        None => Err(anyhow::anyhow!("expected 1 argument, but got none")),
-        Some(file_path) => Ok(file_path),
+    /// Reject disputes of transaction which is disputed N times already
+    #[arg(long, value_name = "N")]
+    max_disputes: Option<usize>,
+
+    /// Abort with error on the first input row which can't be parsed or has amount with too many decimal places
+    #[arg(long)]
+    strict: bool,
+
+    /// Start from the state saved with `--snapshot` by previous run
+    #[arg(long, value_name = "PATH")]
+    restore: Option<PathBuf>,
+
+    /// Save the state of all accounts to PATH after the run, to continue from it with `--restore`
+    #[arg(long, value_name = "PATH")]
+    snapshot: Option<PathBuf>,
+
+    /// Fold balance changes of rows up to sequence number SEQ into one balance before saving the snapshot,
+    /// so event logs don't grow from run to run; balances before SEQ can't be rebuilt then
+    #[arg(long, value_name = "SEQ", requires = "snapshot")]
+    compact_events: Option<u64>,
+
+    /// Log handled transactions to PATH, if the run is interrupted, the next run with the same
+    /// inputs and options replays the log and skips logged rows; the log is removed after the successful run
+    #[arg(long, value_name = "PATH", conflicts_with = "concurrent")]
+    wal: Option<PathBuf>,
+
+    /// Keep transaction history of accounts in sled database at PATH instead of memory,
+    /// history left in it by previous runs is removed
+    #[arg(long, value_name = "PATH")]
+    tx_store: Option<PathBuf>,
+
+    /// Compact transaction history of an account when it holds more than N transactions: transactions
+    /// which are not disputed now keep only type and amount of deposit or withdrawal, and only id after `--dispute-window <N>tx`;
+    /// failed transactions are streamed to `--rejected-report` (CSV) instead of being kept, statements are not kept,
+    /// and event logs and audit trails are cut; history is not complete then, so it can't be saved with `--snapshot`
+    #[arg(long, value_name = "N", conflicts_with_all = ["snapshot", "statement_clients"])]
+    memory_budget: Option<usize>,
+
+    /// Write input rows which can't be parsed to PATH instead of stderr
+    #[arg(long, value_name = "PATH")]
+    errors: Option<PathBuf>,
+
+    /// Print applied transactions of CLIENT in order, with balances after each of them,
+    /// instead of final account states; can be repeated
+    #[arg(long = "statement-client", value_name = "CLIENT")]
+    statement_clients: Vec<AccountID>,
+}
+
+fn parse_overdraft_limit(arg: &str) -> anyhow::Result<(AccountID, Coin)> {
+    let (client, amount) = arg
+        .split_once('=')
+        .ok_or(anyhow::anyhow!("expected CLIENT=AMOUNT, but got '{}'", arg))?;
+    let amount: Coin = amount.trim().parse()?;
+    if amount < Coin::new(0, 0) {
+        return Err(anyhow::anyhow!("Negative overdraft limit"));
+    }
+    Ok((client.trim().parse()?, amount))
+}
+
+fn parse_delimiter(arg: &str) -> anyhow::Result<u8> {
+    match arg {
+        "tab" | "\\t" => Ok(b'\t'),
+        _ if arg.len() == 1 => Ok(arg.as_bytes()[0]),
+        _ => Err(anyhow::anyhow!(
+            "expected single ASCII character or 'tab', but got '{}'",
+            arg
+        )),
+    }
+}
+
+fn parse_column(arg: &str) -> anyhow::Result<(String, String)> {
+    let (field, column) = arg
+        .split_once('=')
+        .ok_or(anyhow::anyhow!("expected FIELD=COLUMN, but got '{}'", arg))?;
+    let field = field.trim();
+    if !InputTransaction::FIELDS.contains(&field) {
+        return Err(anyhow::anyhow!(
+            "unknown field '{}', expected one of {}",
+            field,
+            InputTransaction::FIELDS.join(", ")
+        ));
+    }
+    Ok((field.to_owned(), column.trim().to_owned()))
+}
+
+fn parse_dispute_window(arg: &str) -> anyhow::Result<DisputeWindow> {
+    let arg = arg.trim();
+    if let Some(txs) = arg.strip_suffix("tx") {
+        Ok(DisputeWindow::Transactions(txs.parse()?))
+    } else if let Some(days) = arg.strip_suffix('d') {
+        let seconds = days
+            .parse::<u64>()?
+            .checked_mul(24 * 60 * 60)
+            .ok_or(anyhow::anyhow!("window of {} days is too long", days))?;
+        Ok(DisputeWindow::Seconds(seconds))
+    } else if let Some(seconds) = arg.strip_suffix('s') {
+        Ok(DisputeWindow::Seconds(seconds.parse()?))
+    } else {
+        Err(anyhow::anyhow!(
+            "expected window as <N>d, <N>s or <N>tx, but got '{}'",
+            arg
+        ))
     }
 }
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
+    let args = Args::parse();
+    if args.concurrent && matches!(args.dispute_window, Some(DisputeWindow::Transactions(_))) {
+        return Err(anyhow::anyhow!(
+            "--dispute-window in transactions can't be used with --concurrent, order of rows between inputs is not kept"
+        ));
+    }
     let (sender, receiver) = mpsc::channel(CHANNEL_BUUFER_SIZE);
 
-    let file_path = get_first_arg()?;
-    let data_handle = tokio::spawn(run_reader(file_path, sender));
+    let mut errors: Box<dyn io::Write + Send> = match &args.errors {
+        Some(path) => Box::new(io::BufWriter::new(File::create(path)?)),
+        None => Box::new(io::stderr()),
+    };
+    let inputs = Input::expand(&args.inputs)?;
+    let restored = args.restore.as_deref().map(Snapshot::load).transpose()?;
+    let seq_offset = restored.as_ref().map_or(0, |snapshot| snapshot.seq);
+    let (wal, recovered) = match &args.wal {
+        Some(path) => {
+            let (wal, records) = Wal::open(path)?;
+            (Some(wal), records)
+        }
+        None => (None, Vec::new()),
+    };
+    let processed: Processed = recovered.iter().map(|record| record.tx.seq()).collect();
+    let data_handle = tokio::spawn(async move {
+        let config = ReaderConfig {
+            strict: args.strict,
+            concurrent: args.concurrent,
+            format: args.input_format,
+            layout: CsvLayout {
+                delimiter: args.delimiter,
+                has_headers: !args.no_headers,
+                columns: args.columns,
+            },
+            seq_offset,
+            processed,
+        };
+        let summary = run_readers(inputs, sender, &mut errors, &config).await?;
+        errors.flush()?;
+        anyhow::Ok(summary)
+    });
 
-    let service_handle = tokio::spawn(async {
-        let mut service = Service::new(receiver);
-        service.run().await;
-        service.get_accounts().await
+    let overdraft_limits: HashMap<_, _> = args.overdraft_limits.into_iter().collect();
+    let dispute_policy = DisputePolicy {
+        window: args.dispute_window,
+        max_disputes: args.max_disputes,
+    };
+    let store = match &args.tx_store {
+        Some(path) => StoreBackend::sled(path)?,
+        None => StoreBackend::Memory,
+    };
+    let budget = match args.memory_budget {
+        Some(max_txs) => {
+            let budget = MemoryBudget::new(max_txs);
+            Some(match &args.rejected_report {
+                Some(path) if path.extension().is_some_and(|ext| ext == "json") => {
+                    return Err(anyhow::anyhow!(
+                        "rejected report is streamed as CSV with --memory-budget, but got '{}'",
+                        path.display()
+                    ));
+                }
+                Some(path) => budget.with_rejected(RejectedStream::create(path)?),
+                None => budget,
+            })
+        }
+        None => None,
+    };
+    let service_budget = budget.clone();
+    let save_snapshot = args.snapshot.is_some();
+    let statement_clients = args.statement_clients.clone();
+    let compact_events = args.compact_events;
+    let service_handle = tokio::spawn(async move {
+        let mut service = Service::new(receiver)
+            .with_overdraft_limits(overdraft_limits)
+            .with_dispute_policy(dispute_policy)
+            .with_statement_clients(statement_clients)
+            .with_store(store);
+        if let Some(budget) = service_budget {
+            service = service.with_memory_budget(budget);
+        }
+        if let Some(snapshot) = restored {
+            service = service.with_snapshot(snapshot);
+        }
+        service.recover(recovered)?;
+        if let Some(wal) = wal {
+            service = service.with_wal(wal);
+        }
+        service.run().await?;
+        let snapshot = if save_snapshot {
+            if let Some(seq) = compact_events {
+                service.compact_events(seq).await;
+            }
+            Some(service.snapshot().await?)
+        } else {
+            None
+        };
+        anyhow::Ok((service.get_accounts().await, snapshot))
     });
 
-    let (read_res, accounts) = tokio::join!(data_handle, service_handle);
+    let (read_res, service_res) = tokio::join!(data_handle, service_handle);
+    let (accounts, snapshot) = service_res??;
+
+    let summary = read_res??;
+    if summary.rows_rejected > 0 {
+        eprintln!(
+            "read {} rows: {} parsed, {} rejected",
+            summary.rows_read, summary.rows_parsed, summary.rows_rejected
+        );
+    }
+    if summary.rows_skipped > 0 {
+        eprintln!(
+            "recovered {} rows from write-ahead log",
+            summary.rows_skipped
+        );
+    }
+    let mut accounts = accounts.into_values().collect::<Vec<_>>();
+    sort_accounts(&mut accounts, args.sort_by);
 
-    read_res??;
-    let accounts = accounts?;
+    // invariants are checked in debug builds and with `invariants` feature
+    if let Some(violation) = accounts
+        .iter()
+        .filter_map(|acc| acc.invariant_violation())
+        .min_by_key(|violation| violation.tx.seq())
+    {
+        return Err(anyhow::anyhow!("ledger invariant violated: {}", violation));
+    }
 
-    write_results(accounts.values().cloned().collect::<Vec<_>>())?;
+    if let (Some(path), Some(snapshot)) = (args.snapshot, snapshot) {
+        snapshot.save(&path)?;
+    }
+    match (&budget, args.rejected_report) {
+        (Some(budget), _) => {
+            if let Some(rejected) = &budget.rejected {
+                rejected.flush()?;
+            }
+            eprintln!("memory budget: {}", budget.metrics);
+        }
+        (None, Some(path)) => write_rejected(&path, &accounts)?,
+        (None, None) => {}
+    }
+    if args.statement_clients.is_empty() {
+        write_results(accounts, args.output_format)?;
+    } else {
+        write_statement(&accounts, &args.statement_clients, args.output_format)?;
+    }
+    if let Some(path) = args.wal {
+        std::fs::remove_file(path)?;
+    }
 
     Ok(())
 }
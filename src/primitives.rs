@@ -1,12 +1,27 @@
 use crate::{
-    account::Account,
-    transaction::{InputTransaction, Transaction},
+    account::{Account, StatementEntry},
+    transaction::{
+        InputTransaction, JsonInputTransaction, RejectionReason, Transaction, TransactionType,
+    },
+    wal::Processed,
 };
-use csv_async::AsyncReaderBuilder;
+use anyhow::anyhow;
+use async_compression::tokio::bufread::{GzipDecoder, ZstdDecoder};
+use csv_async::{AsyncReaderBuilder, ByteRecord};
 use rust_decimal::Decimal;
-use std::{ffi::OsString, io};
-use tokio::{fs::File, sync::mpsc};
-use tokio_stream::StreamExt;
+use serde::Serialize;
+use std::{
+    ffi::OsString,
+    fmt, fs, io,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{Arc, Mutex},
+};
+use tokio::{
+    fs::File,
+    io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, BufReader},
+    sync::mpsc,
+};
 
 pub const CHANNEL_BUUFER_SIZE: usize = 100;
 pub const PRECISION: u32 = 4;
@@ -18,35 +33,778 @@ pub type Coin = Decimal;
 #[derive(Debug)]
 pub enum Message {
     Tx(Transaction),
+    Rejected(Transaction, RejectionReason), // transaction rejected before reaching the account, stored as failed
     Stop,
 }
 
-pub fn write_results(v: Vec<Account>) -> anyhow::Result<()> {
-    let mut wtr = csv::Writer::from_writer(io::stdout());
+/// Format of the account states output
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+pub enum OutputFormat {
+    #[default]
+    Csv,
+    Json,  // JSON array of accounts
+    Jsonl, // JSON Lines, one account per line
+}
+
+impl FromStr for OutputFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(input: &str) -> anyhow::Result<Self> {
+        match input.to_lowercase().trim() {
+            "csv" => Ok(OutputFormat::Csv),
+            "json" => Ok(OutputFormat::Json),
+            "jsonl" => Ok(OutputFormat::Jsonl),
+            _ => Err(anyhow!("Unknown OutputFormat: {}", input)),
+        }
+    }
+}
+
+/// Order of the account states output
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+pub enum SortBy {
+    #[default]
+    Client, // ascending client id
+    Total,     // descending, then by client id
+    Available, // descending, then by client id
+    Held,      // descending, then by client id
+}
+
+impl FromStr for SortBy {
+    type Err = anyhow::Error;
+
+    fn from_str(input: &str) -> anyhow::Result<Self> {
+        match input.to_lowercase().trim() {
+            "client" => Ok(SortBy::Client),
+            "total" => Ok(SortBy::Total),
+            "available" => Ok(SortBy::Available),
+            "held" => Ok(SortBy::Held),
+            _ => Err(anyhow!("Unknown SortBy: {}", input)),
+        }
+    }
+}
+
+/// Sort accounts for output, order is the same for every run
+pub fn sort_accounts(accounts: &mut [Account], sort_by: SortBy) {
+    accounts.sort_by(|a, b| {
+        let by_amount = match sort_by {
+            SortBy::Client => std::cmp::Ordering::Equal,
+            SortBy::Total => b.total().cmp(&a.total()),
+            SortBy::Available => b.available().cmp(&a.available()),
+            SortBy::Held => b.held().cmp(&a.held()),
+        };
+        by_amount.then(a.id().cmp(&b.id()))
+    });
+}
+
+pub fn write_results(v: Vec<Account>, format: OutputFormat) -> anyhow::Result<()> {
+    write_results_to(io::stdout().lock(), v, format)
+}
+
+/// Write account states to `writer`, amounts are written as strings in JSON formats to keep precision
+pub fn write_results_to<W: io::Write>(
+    writer: W,
+    v: Vec<Account>,
+    format: OutputFormat,
+) -> anyhow::Result<()> {
+    write_rows(writer, v, format)
+}
 
-    for i in v {
-        wtr.serialize(i)?;
+fn write_rows<W: io::Write, T: Serialize>(
+    mut writer: W,
+    rows: Vec<T>,
+    format: OutputFormat,
+) -> anyhow::Result<()> {
+    match format {
+        OutputFormat::Csv => {
+            let mut wtr = csv::Writer::from_writer(&mut writer);
+            for row in rows {
+                wtr.serialize(row)?;
+            }
+            wtr.flush()?;
+        }
+        OutputFormat::Json => {
+            serde_json::to_writer(&mut writer, &rows)?;
+            writeln!(writer)?;
+        }
+        OutputFormat::Jsonl => {
+            for row in rows {
+                serde_json::to_writer(&mut writer, &row)?;
+                writeln!(writer)?;
+            }
+        }
     }
-    wtr.flush()?;
+    writer.flush()?;
     Ok(())
 }
 
-pub async fn run_reader(file_path: OsString, sender: mpsc::Sender<Message>) -> anyhow::Result<()> {
-    let file = File::open(file_path).await?;
-    let mut rdr = AsyncReaderBuilder::new()
-        .flexible(true)
-        .create_deserializer(file);
+/// Row of the account statement
+#[derive(Debug, Serialize)]
+pub struct StatementRow {
+    client: AccountID,
+    tx: TxID,
+    #[serde(rename = "type")]
+    tx_type: TransactionType,
+    amount: Option<Coin>,
+    input: Option<String>, // name of the input, if there are several inputs
+    line: u64,
+    available: Coin,
+    held: Coin,
+    total: Coin,
+    locked: bool,
+}
+
+impl StatementRow {
+    fn new(client: AccountID, entry: &StatementEntry) -> Self {
+        Self {
+            client,
+            tx: entry.tx.id(),
+            tx_type: entry.tx.tx_type(),
+            amount: entry.amount,
+            input: entry.tx.input().map(str::to_owned),
+            line: entry.tx.line(),
+            available: entry.available,
+            held: entry.held,
+            total: entry.total,
+            locked: entry.locked,
+        }
+    }
+}
+
+/// Write statements of `clients` to stdout, in order of client ids
+///
+/// clients without account are skipped
+pub fn write_statement(
+    accounts: &[Account],
+    clients: &[AccountID],
+    format: OutputFormat,
+) -> anyhow::Result<()> {
+    write_statement_to(io::stdout().lock(), accounts, clients, format)
+}
+
+pub fn write_statement_to<W: io::Write>(
+    writer: W,
+    accounts: &[Account],
+    clients: &[AccountID],
+    format: OutputFormat,
+) -> anyhow::Result<()> {
+    let mut accounts = accounts
+        .iter()
+        .filter(|acc| clients.contains(&acc.id()))
+        .collect::<Vec<_>>();
+    accounts.sort_by_key(|acc| acc.id());
+    let rows = accounts
+        .iter()
+        .flat_map(|acc| {
+            acc.statement()
+                .iter()
+                .map(|entry| StatementRow::new(acc.id(), entry))
+        })
+        .collect::<Vec<_>>();
+    write_rows(writer, rows, format)
+}
+
+/// Row of the rejected transactions report
+#[derive(Debug, Serialize)]
+pub struct RejectedTransaction {
+    client: AccountID,
+    tx: TxID,
+    #[serde(rename = "type")]
+    tx_type: TransactionType,
+    amount: Option<Coin>,
+    input: Option<String>, // name of the input, if there are several inputs
+    line: u64,
+    reason: RejectionReason,
+}
+
+impl From<&(Transaction, RejectionReason)> for RejectedTransaction {
+    fn from((tx, reason): &(Transaction, RejectionReason)) -> Self {
+        Self {
+            client: tx.account(),
+            tx: tx.id(),
+            tx_type: tx.tx_type(),
+            amount: tx.input_amount(),
+            input: tx.input().map(str::to_owned),
+            line: tx.line(),
+            reason: *reason,
+        }
+    }
+}
+
+/// Rejected transactions report written as transactions are rejected, instead of after the run
+///
+/// rows of different accounts are interleaved in order of rejection, the report is always CSV
+#[derive(Debug)]
+pub struct RejectedStream {
+    wtr: Mutex<csv::Writer<io::BufWriter<fs::File>>>,
+}
+
+impl RejectedStream {
+    pub fn create(path: &Path) -> anyhow::Result<Self> {
+        let file = io::BufWriter::new(fs::File::create(path)?);
+        Ok(Self {
+            wtr: Mutex::new(csv::Writer::from_writer(file)),
+        })
+    }
+
+    pub fn write(&self, failed: &(Transaction, RejectionReason)) -> anyhow::Result<()> {
+        let mut wtr = self.wtr.lock().expect("rejected report lock poisoned");
+        wtr.serialize(RejectedTransaction::from(failed))?;
+        Ok(())
+    }
+
+    pub fn flush(&self) -> anyhow::Result<()> {
+        self.wtr
+            .lock()
+            .expect("rejected report lock poisoned")
+            .flush()?;
+        Ok(())
+    }
+}
+
+/// Write every rejected transaction of the accounts to the report file
+///
+/// report is written as JSON array if file has `.json` extension, as CSV otherwise
+pub fn write_rejected(path: &Path, accounts: &[Account]) -> anyhow::Result<()> {
+    let mut rows = Vec::new();
+    for acc in accounts {
+        rows.extend(acc.failed()?.iter().map(RejectedTransaction::from));
+    }
+
+    let file = io::BufWriter::new(fs::File::create(path)?);
+    if path.extension().is_some_and(|ext| ext == "json") {
+        serde_json::to_writer_pretty(file, &rows)?;
+    } else {
+        let mut wtr = csv::Writer::from_writer(file);
+        for row in rows {
+            wtr.serialize(row)?;
+        }
+        wtr.flush()?;
+    }
+    Ok(())
+}
+
+/// Counters of input rows handled by reader
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+pub struct ReadSummary {
+    pub rows_read: u64,     // all rows except header and blank lines
+    pub rows_parsed: u64,   // rows sent to the service as transactions
+    pub rows_rejected: u64, // rows which can't be parsed into transaction
+    pub rows_skipped: u64,  // parsed rows which are processed already by interrupted run
+}
+
+impl ReadSummary {
+    fn add(&mut self, other: &Self) {
+        self.rows_read += other.rows_read;
+        self.rows_parsed += other.rows_parsed;
+        self.rows_rejected += other.rows_rejected;
+        self.rows_skipped += other.rows_skipped;
+    }
+}
+
+/// Options of the input reader
+#[derive(Debug, Default, Clone)]
+pub struct ReaderConfig {
+    pub strict: bool,     // abort reading on the first row which can't be parsed
+    pub concurrent: bool, // read all inputs at once, only correct if every client is in one input
+    pub format: Option<InputFormat>, // format of all inputs, detected by file extension if not set
+    pub layout: CsvLayout,
+    pub seq_offset: u64, // sequence numbers of transactions start after it, to continue from snapshot
+    pub processed: Processed, // transactions recovered from WAL, which are not sent again
+}
+
+/// Layout of CSV inputs
+#[derive(Debug, Clone)]
+pub struct CsvLayout {
+    pub delimiter: u8,
+    pub has_headers: bool, // without headers columns are in `InputTransaction::FIELDS` order unless mapped
+    pub columns: Vec<(String, String)>, // input field and its column: header name, or 1-based position without headers
+}
+
+impl Default for CsvLayout {
+    fn default() -> Self {
+        Self {
+            delimiter: b',',
+            has_headers: true,
+            columns: Vec::new(),
+        }
+    }
+}
+
+impl CsvLayout {
+    /// Rename input headers to field names, headers which are not mapped are kept as is
+    ///
+    /// header named as a mapped field, but not mapped to it, is ignored
+    fn map_headers(&self, headers: &ByteRecord) -> anyhow::Result<ByteRecord> {
+        let mut names = headers
+            .iter()
+            .map(|name| String::from_utf8_lossy(name).trim().to_owned())
+            .collect::<Vec<_>>();
+        for name in names.iter_mut() {
+            if self.columns.iter().any(|(field, _)| field == name) {
+                name.clear();
+            }
+        }
+        for (field, column) in &self.columns {
+            let pos = headers
+                .iter()
+                .position(|name| String::from_utf8_lossy(name).trim() == column)
+                .ok_or(anyhow!("Column '{}' not found in headers", column))?;
+            names[pos].clone_from(field);
+        }
+        Ok(ByteRecord::from(names))
+    }
+
+    /// Field names by column position for input without headers
+    fn positional_headers(&self) -> anyhow::Result<ByteRecord> {
+        // fields which are not mapped keep their default position
+        let mut positions = InputTransaction::FIELDS
+            .iter()
+            .enumerate()
+            .filter(|(_, &field)| self.columns.iter().all(|(mapped, _)| mapped != field))
+            .map(|(pos, &field)| (field, pos))
+            .collect::<Vec<_>>();
+        for (field, column) in &self.columns {
+            let pos = match column.parse::<usize>() {
+                Ok(pos) if pos > 0 => pos - 1,
+                _ => {
+                    return Err(anyhow!(
+                        "Expected column position from 1, but got '{}'",
+                        column
+                    ))
+                }
+            };
+            positions.retain(|&(_, p)| p != pos);
+            positions.push((field, pos));
+        }
+
+        let len = positions
+            .iter()
+            .map(|&(_, pos)| pos + 1)
+            .max()
+            .unwrap_or_default();
+        let mut headers = vec![""; len];
+        for (field, pos) in positions {
+            headers[pos] = field;
+        }
+        Ok(ByteRecord::from(headers))
+    }
+}
+
+/// Format of the transactions input
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+pub enum InputFormat {
+    #[default]
+    Csv,
+    Jsonl, // JSON Lines, one transaction object per line
+}
+
+impl FromStr for InputFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(input: &str) -> anyhow::Result<Self> {
+        match input.to_lowercase().trim() {
+            "csv" => Ok(InputFormat::Csv),
+            "jsonl" | "ndjson" => Ok(InputFormat::Jsonl),
+            _ => Err(anyhow!("Unknown InputFormat: {}", input)),
+        }
+    }
+}
+
+/// Source of transactions
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Input {
+    Stdin,
+    File(PathBuf),
+}
+
+impl Input {
+    /// Expand input arguments into inputs in the given order
+    ///
+    /// `-` is stdin, arguments with `*`, `?` or `[` are glob patterns expanded in alphabetical order,
+    /// pattern which matches no files is an error
+    pub fn expand(args: &[OsString]) -> anyhow::Result<Vec<Self>> {
+        let mut inputs = Vec::with_capacity(args.len());
+        for arg in args {
+            match arg.to_str() {
+                Some("-") => inputs.push(Input::Stdin),
+                Some(pattern) if pattern.contains(['*', '?', '[']) => {
+                    let len = inputs.len();
+                    for path in glob::glob(pattern)? {
+                        inputs.push(Input::File(path?));
+                    }
+                    if inputs.len() == len {
+                        return Err(anyhow!("No files match '{}'", pattern));
+                    }
+                }
+                _ => inputs.push(Input::File(arg.into())),
+            }
+        }
+        Ok(inputs)
+    }
+
+    /// Format detected by file extension: `.jsonl` and `.ndjson` are JSON Lines, everything else is CSV
+    ///
+    /// compression extension is skipped, so `.jsonl.gz` is JSON Lines
+    pub fn format(&self) -> InputFormat {
+        match self {
+            Input::File(path) => {
+                let path = match path.extension().and_then(|ext| ext.to_str()) {
+                    Some("gz" | "zst") => path.with_extension(""),
+                    _ => path.clone(),
+                };
+                path.extension()
+                    .and_then(|ext| ext.to_str()?.parse().ok())
+                    .unwrap_or_default()
+            }
+            Input::Stdin => InputFormat::default(),
+        }
+    }
+
+    /// Open input for reading, gzip and zstd inputs are decompressed while reading
+    async fn open(&self) -> anyhow::Result<Box<dyn AsyncRead + Unpin + Send>> {
+        match self {
+            Input::Stdin => decompress(tokio::io::stdin()).await,
+            Input::File(path) => decompress(File::open(path).await?).await,
+        }
+    }
+}
+
+impl fmt::Display for Input {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Input::Stdin => write!(f, "stdin"),
+            Input::File(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
 
-    let mut records = rdr.deserialize::<InputTransaction>();
-    while let Some(Ok(record)) = records.next().await {
-        if let Ok(tx) = Transaction::try_from(record) {
-            sender.send(Message::Tx(tx)).await.expect("service stopped");
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+
+/// Wrap input into streaming decoder if it starts with gzip or zstd magic bytes, plain input is returned as is
+async fn decompress<R: AsyncRead + Unpin + Send + 'static>(
+    mut input: R,
+) -> anyhow::Result<Box<dyn AsyncRead + Unpin + Send>> {
+    // single read may return less than the magic bytes, e.g. from pipe
+    let mut head = [0; ZSTD_MAGIC.len()];
+    let mut len = 0;
+    while len < head.len() {
+        match input.read(&mut head[len..]).await? {
+            0 => break,
+            n => len += n,
+        }
+    }
+    let head = &head[..len];
+    let input = BufReader::new(io::Cursor::new(head.to_vec()).chain(input));
+    if head.starts_with(GZIP_MAGIC) {
+        let mut decoder = GzipDecoder::new(input);
+        decoder.multiple_members(true); // concatenated gzip files
+        Ok(Box::new(decoder))
+    } else if head.starts_with(ZSTD_MAGIC) {
+        let mut decoder = ZstdDecoder::new(input);
+        decoder.multiple_members(true);
+        Ok(Box::new(decoder))
+    } else {
+        Ok(Box::new(input))
+    }
+}
+
+/// Read transactions from CSV file and send them to the service
+///
+/// rows which can't be parsed are skipped and reported to `errors` with line number and raw record,
+/// reading stops only on IO error
+///
+/// in strict mode reading stops with error on the first row which can't be parsed
+pub async fn run_reader<W: io::Write>(
+    file_path: OsString,
+    sender: mpsc::Sender<Message>,
+    errors: &mut W,
+    config: &ReaderConfig,
+) -> anyhow::Result<ReadSummary> {
+    run_readers(vec![Input::File(file_path.into())], sender, errors, config).await
+}
+
+/// Read transactions from all inputs and send them to the service, then stop the service
+///
+/// inputs are read one after another, or all at once in concurrent mode,
+/// errors are reported the same way as in `run_reader`, prefixed with input name if there are several inputs
+pub async fn run_readers<W: io::Write>(
+    inputs: Vec<Input>,
+    sender: mpsc::Sender<Message>,
+    errors: &mut W,
+    config: &ReaderConfig,
+) -> anyhow::Result<ReadSummary> {
+    let named = inputs.len() > 1;
+    let mut summary = ReadSummary::default();
+    if config.concurrent {
+        // errors of every input are buffered to keep them grouped by input
+        // inputs take turns in sequence numbers, so they don't overlap and keep order within every input
+        let step = inputs.len() as u64;
+        let handles = inputs
+            .into_iter()
+            .enumerate()
+            .map(|(i, input)| {
+                let sender = sender.clone();
+                let config = config.clone();
+                tokio::spawn(async move {
+                    let mut errors = Vec::new();
+                    let mut summary = ReadSummary::default();
+                    let seqs = (config.seq_offset + i as u64, step);
+                    let res = read_input(
+                        &input,
+                        named,
+                        seqs,
+                        &sender,
+                        &mut errors,
+                        &config,
+                        &mut summary,
+                    )
+                    .await;
+                    (res.map(|()| summary), errors)
+                })
+            })
+            .collect::<Vec<_>>();
+        for handle in handles {
+            let (res, input_errors) = handle.await?;
+            errors.write_all(&input_errors)?;
+            summary.add(&res?);
+        }
+    } else {
+        let seqs = (config.seq_offset, 1);
+        for input in &inputs {
+            read_input(input, named, seqs, &sender, errors, config, &mut summary).await?;
         }
-        // TODO: handle parsing errors
     }
     sender
         .send(Message::Stop)
         .await
-        .expect("failed to gracefully stop");
-    Ok(())
+        .map_err(|_| anyhow!("service stopped before the end of input"))?;
+    Ok(summary)
+}
+
+/// Read transactions from one input, `summary` is continued so transaction order is kept across inputs
+///
+/// sequence numbers of rows are `start + 1`, `start + 1 + step` and so on, counting rows of `summary`
+async fn read_input<W: io::Write>(
+    input: &Input,
+    named: bool,
+    (seq_start, seq_step): (u64, u64),
+    sender: &mpsc::Sender<Message>,
+    errors: &mut W,
+    config: &ReaderConfig,
+    summary: &mut ReadSummary,
+) -> anyhow::Result<()> {
+    let name: Option<Arc<str>> = named.then(|| input.to_string().into());
+    let mut rdr = InputReader {
+        prefix: match &name {
+            Some(name) => format!("{}: ", name),
+            None => String::new(),
+        },
+        name,
+        seq_start,
+        seq_step,
+        sender,
+        errors,
+        config,
+        summary,
+    };
+    let format = config.format.unwrap_or_else(|| input.format());
+    match format {
+        InputFormat::Csv => rdr.read_csv(input.open().await?).await,
+        InputFormat::Jsonl => rdr.read_jsonl(input.open().await?).await,
+    }
+}
+
+/// Parses rows of one input and sends them to the service
+struct InputReader<'a, W> {
+    prefix: String,         // input name for error messages
+    name: Option<Arc<str>>, // input name for transactions, if there are several inputs
+    seq_start: u64,         // sequence number before the first row
+    seq_step: u64,          // distance between sequence numbers of rows
+    sender: &'a mpsc::Sender<Message>,
+    errors: &'a mut W,
+    config: &'a ReaderConfig,
+    summary: &'a mut ReadSummary,
+}
+
+impl<W: io::Write> InputReader<'_, W> {
+    async fn read_csv<R: AsyncRead + Unpin + Send>(&mut self, input: R) -> anyhow::Result<()> {
+        let layout = &self.config.layout;
+        let mut rdr = AsyncReaderBuilder::new()
+            .flexible(true)
+            .delimiter(layout.delimiter)
+            .has_headers(layout.has_headers)
+            .create_reader(input);
+        let headers = if layout.has_headers {
+            layout.map_headers(rdr.byte_headers().await?)?
+        } else {
+            layout.positional_headers()?
+        };
+
+        let mut record = ByteRecord::new();
+        while rdr.read_byte_record(&mut record).await? {
+            let line = record_line(&record, rdr.position().line());
+            let parsed = parse_record(&record, &headers, self.config.strict);
+            self.handle(parsed, line, || raw_record(&record, layout.delimiter))
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Read JSON object per line, blank lines are skipped
+    ///
+    /// lines are read as bytes, so line with invalid UTF-8 is reported as row which can't be parsed
+    async fn read_jsonl<R: AsyncRead + Unpin + Send>(&mut self, input: R) -> anyhow::Result<()> {
+        let mut input = BufReader::new(input);
+        let mut buf = Vec::new();
+        let mut line = 0;
+        while input.read_until(b'\n', &mut buf).await? > 0 {
+            line += 1;
+            let row = buf.strip_suffix(b"\n").unwrap_or(&buf);
+            let row = row.strip_suffix(b"\r").unwrap_or(row);
+            if row.trim_ascii().is_empty() {
+                buf.clear();
+                continue;
+            }
+            let parsed = std::str::from_utf8(row)
+                .map_err(anyhow::Error::from)
+                .and_then(|row| parse_json(row, self.config.strict));
+            self.handle(parsed, line, || String::from_utf8_lossy(row).into_owned())
+                .await?;
+            buf.clear();
+        }
+        Ok(())
+    }
+
+    /// Send parsed transaction to the service or report the row which can't be parsed
+    async fn handle(
+        &mut self,
+        parsed: anyhow::Result<Transaction>,
+        line: u64,
+        raw: impl Fn() -> String,
+    ) -> anyhow::Result<()> {
+        self.summary.rows_read += 1;
+        match parsed {
+            Ok(tx) => {
+                self.summary.rows_parsed += 1;
+                let seq = self.seq_start + 1 + (self.summary.rows_read - 1) * self.seq_step;
+                if self.config.processed.contains(seq) {
+                    self.summary.rows_skipped += 1;
+                    return Ok(());
+                }
+                let tx = tx
+                    .with_line(line)
+                    .with_seq(seq)
+                    .with_input(self.name.clone());
+                self.sender
+                    .send(Message::Tx(tx))
+                    .await
+                    .map_err(|_| anyhow!("service stopped before the end of input"))?;
+            }
+            Err(err) if self.config.strict => {
+                return Err(anyhow!("{}line {}: {}: {}", self.prefix, line, err, raw()));
+            }
+            Err(err) => {
+                self.summary.rows_rejected += 1;
+                writeln!(
+                    self.errors,
+                    "{}line {}: {}: {}",
+                    self.prefix,
+                    line,
+                    err,
+                    raw()
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn parse_json(row: &str, strict: bool) -> anyhow::Result<Transaction> {
+    let input = InputTransaction::from(serde_json::from_str::<JsonInputTransaction>(row)?);
+    if strict {
+        Transaction::try_from_strict(input)
+    } else {
+        Transaction::try_from(input)
+    }
+}
+
+fn parse_record(
+    record: &ByteRecord,
+    headers: &ByteRecord,
+    strict: bool,
+) -> anyhow::Result<Transaction> {
+    let input = record.deserialize::<InputTransaction>(Some(headers))?;
+    if strict {
+        Transaction::try_from_strict(input)
+    } else {
+        Transaction::try_from(input)
+    }
+}
+
+/// Record as it was in the input, invalid UTF-8 is replaced
+fn raw_record(record: &ByteRecord, delimiter: u8) -> String {
+    record
+        .iter()
+        .map(String::from_utf8_lossy)
+        .collect::<Vec<_>>()
+        .join(&char::from(delimiter).to_string())
+}
+
+/// Line on which the record starts
+///
+/// record position points to the line after previous record, which is wrong if blank lines were skipped,
+/// so line is calculated back from `next_line` (reader position after the record)
+fn record_line(record: &ByteRecord, next_line: u64) -> u64 {
+    let start = record.position().map_or(0, |pos| pos.line());
+    let inner_lines = record
+        .iter()
+        .map(|field| field.iter().filter(|&&b| b == b'\n').count() as u64)
+        .sum::<u64>();
+    start.max(next_line.saturating_sub(1 + inner_lines))
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_record_line_after_blank_lines() {
+        let data = "type,client,tx,amount\ndeposit,1,1,1.0\n\n\ndeposit,1,2,1.0\n\"deposit\n\",1,3,1.0\ndeposit,1,4,1.0";
+        let mut rdr = AsyncReaderBuilder::new()
+            .flexible(true)
+            .create_reader(data.as_bytes());
+
+        let mut lines = Vec::new();
+        let mut record = ByteRecord::new();
+        while rdr.read_byte_record(&mut record).await.unwrap() {
+            lines.push(record_line(&record, rdr.position().line()));
+        }
+
+        assert_eq!(lines, vec![2, 5, 6, 8]);
+    }
+
+    #[tokio::test]
+    async fn test_decompress_magic_split_between_reads() {
+        let data = "type,client,tx,amount\ndeposit,1,1,1.0\n";
+        let mut compressed = Vec::new();
+        async_compression::tokio::bufread::ZstdEncoder::new(data.as_bytes())
+            .read_to_end(&mut compressed)
+            .await
+            .unwrap();
+
+        // chained reader returns only the first byte on the first read
+        let (first, rest) = compressed.split_at(1);
+        let input = io::Cursor::new(first.to_vec()).chain(io::Cursor::new(rest.to_vec()));
+        let mut decompressed = String::new();
+        decompress(input)
+            .await
+            .unwrap()
+            .read_to_string(&mut decompressed)
+            .await
+            .unwrap();
+
+        assert_eq!(decompressed, data);
+    }
 }
@@ -1,9 +1,16 @@
-use crate::account::Account;
-use crate::primitives::{AccountID, Message, CHANNEL_BUUFER_SIZE};
-use crate::transaction::Transaction;
-use std::collections::HashMap;
+use crate::account::{Account, Balance, DisputePolicy};
+use crate::budget::MemoryBudget;
+use crate::primitives::{AccountID, Coin, Message, TxID, CHANNEL_BUUFER_SIZE};
+use crate::snapshot::Snapshot;
+use crate::store::StoreBackend;
+use crate::transaction::{RejectionReason, Transaction, TransactionType};
+use crate::wal::{Wal, WalRecord};
+use anyhow::{anyhow, Context};
+use roaring::RoaringBitmap;
+use std::collections::{hash_map::Entry, BTreeMap, HashMap, HashSet};
 use std::sync::Arc;
-use tokio::sync::{mpsc, Mutex};
+use tokio::sync::{mpsc, Mutex, Notify};
+use tokio::task::JoinHandle;
 
 pub struct Service {
     input: mpsc::Receiver<Message>,
@@ -11,6 +18,17 @@ pub struct Service {
     // TODO: can be combined with accounts_channels in separate structure with option chanel
     accounts: HashMap<AccountID, Arc<Mutex<Account>>>,
     accounts_channels: HashMap<AccountID, mpsc::Sender<Message>>,
+    tasks: Vec<JoinHandle<anyhow::Result<()>>>, // account tasks, they stop before `Stop` only on error
+
+    overdraft_limits: HashMap<AccountID, Coin>, // accounts allowed to go below zero on withdrawal
+    tx_ids: Arc<TxIds>,                         // owner account of every deposit and withdrawal id
+    dispute_policy: DisputePolicy,              // limits for disputes of all accounts
+    restored: HashMap<AccountID, Account>,      // accounts from snapshot not used in this run yet
+    seq: u64,                                   // sequence number of the last transaction
+    wal: Option<Arc<Wal>>,                      // log of transactions handled by accounts
+    store: StoreBackend,                        // where accounts keep transaction history
+    budget: Option<MemoryBudget>,               // limit of transaction history of every account
+    statement_clients: HashSet<AccountID>,      // accounts which keep statements
 }
 
 impl Service {
@@ -19,86 +37,572 @@ impl Service {
             accounts: HashMap::new(),
             input: receiver,
             accounts_channels: HashMap::new(),
+            tasks: Vec::new(),
+            overdraft_limits: HashMap::new(),
+            tx_ids: Arc::default(),
+            dispute_policy: DisputePolicy::default(),
+            restored: HashMap::new(),
+            seq: 0,
+            wal: None,
+            store: StoreBackend::default(),
+            budget: None,
+            statement_clients: HashSet::new(),
+        }
+    }
+
+    /// Allow listed accounts to overdraw available funds up to the given limit
+    ///
+    /// accounts not listed can't withdraw more than they have available
+    pub fn with_overdraft_limits(self, overdraft_limits: HashMap<AccountID, Coin>) -> Self {
+        Self {
+            overdraft_limits,
+            ..self
+        }
+    }
+
+    /// Limit disputes of all accounts by window after deposit or withdrawal and number of disputes
+    pub fn with_dispute_policy(self, dispute_policy: DisputePolicy) -> Self {
+        Self {
+            dispute_policy,
+            ..self
+        }
+    }
+
+    /// Continue from the state of previous run
+    ///
+    /// overdraft limits and dispute policy of the service are applied to restored accounts
+    pub fn with_snapshot(self, snapshot: Snapshot) -> Self {
+        Self {
+            restored: snapshot
+                .accounts
+                .into_iter()
+                .map(|state| (state.id(), Account::from(state)))
+                .collect(),
+            tx_ids: {
+                let tx_ids = TxIds::from(snapshot.tx_ids);
+                if self.budget.is_some() {
+                    tx_ids.compact();
+                }
+                Arc::new(tx_ids)
+            },
+            seq: snapshot.seq,
+            ..self
+        }
+    }
+
+    /// Write every transaction handled by accounts to `wal`
+    pub fn with_wal(self, wal: Wal) -> Self {
+        Self {
+            wal: Some(Arc::new(wal)),
+            ..self
+        }
+    }
+
+    /// Keep statements of listed accounts, other accounts don't keep them
+    pub fn with_statement_clients(self, clients: impl IntoIterator<Item = AccountID>) -> Self {
+        Self {
+            statement_clients: clients.into_iter().collect(),
+            ..self
+        }
+    }
+
+    /// Keep transaction history of accounts in `store` instead of memory
+    pub fn with_store(self, store: StoreBackend) -> Self {
+        Self { store, ..self }
+    }
+
+    /// Keep transaction history of every account within `budget`, owners of transaction ids are kept compact
+    pub fn with_memory_budget(self, budget: MemoryBudget) -> Self {
+        self.tx_ids.compact();
+        Self {
+            budget: Some(budget),
+            ..self
+        }
+    }
+
+    /// Replay records of WAL left by interrupted run, service should be configured the same way as before
+    ///
+    /// records are replayed in input order, so transaction ids get the same owners as in the interrupted run,
+    /// fails if transaction gets different result than in the log;
+    ///
+    /// rejections of the id check are replayed as they are, as rows they depend on can be missing in the log,
+    /// e.g. deposit which took the id, when the log was cut before its record
+    pub fn recover(&mut self, mut records: Vec<WalRecord>) -> anyhow::Result<()> {
+        records.sort_by_key(|record| record.tx.seq());
+        let mut recovered = HashMap::new();
+        for WalRecord {
+            tx,
+            rejected,
+            by_service,
+        } in records
+        {
+            self.seq = self.seq.max(tx.seq());
+            let account = match recovered.entry(tx.account()) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => entry.insert(self.take_account(tx.account())?),
+            };
+            // claims of replayed transactions are resolved right away, so there is nothing to wait for
+            let claim = match rejected {
+                Some(reason) if by_service => Err(reason),
+                _ => self.tx_ids.try_claim(&tx).unwrap_or(Ok(())),
+            };
+            let res = match claim {
+                Ok(()) => {
+                    let res = account.try_process(&tx)?.err();
+                    self.tx_ids.resolve(&tx, res.is_none());
+                    res
+                }
+                Err(reason) => {
+                    account.reject(&tx, reason)?;
+                    Some(reason)
+                }
+            };
+            if res != rejected {
+                return Err(anyhow!(
+                    "WAL replay diverged on transaction {} of client {} at line {}: {:?} instead of {:?}",
+                    tx.id(),
+                    tx.account(),
+                    tx.line(),
+                    res,
+                    rejected
+                ));
+            }
         }
+        // recovered accounts are configured and their history is in the store already,
+        // so they are not taken again when their task is started
+        self.accounts.extend(
+            recovered
+                .into_iter()
+                .map(|(id, account)| (id, Arc::new(Mutex::new(account)))),
+        );
+        Ok(())
     }
 
     /// Wait for messages from reader, parse them and process transaction
-    pub async fn run(&mut self) {
+    ///
+    /// fails if an account task fails, e.g. on WAL write error, then input is closed and other accounts are stopped
+    pub async fn run(&mut self) -> anyhow::Result<()> {
         while let Some(input) = self.input.recv().await {
-            match input {
-                Message::Tx(tx) => {
-                    let _ = self.process_tx(tx).await; // TODO: handle error
-                }
-                Message::Stop => {
-                    for acc_sender in self.accounts_channels.values() {
-                        let _ = acc_sender.send(Message::Stop).await;
-                        acc_sender.closed().await;
-                    }
-                    break;
+            let res = match input {
+                Message::Tx(tx) => self.process_tx(tx).await,
+                Message::Rejected(tx, reason) => {
+                    self.send_to_account(tx.account(), Message::Rejected(tx, reason))
+                        .await
                 }
+                Message::Stop => break,
+            };
+            if let Err(err) = res {
+                self.input.close();
+                self.stop_accounts().await?; // error of the failed task is the cause
+                return Err(err);
+            }
+        }
+        self.stop_accounts().await
+    }
+
+    /// Stop account tasks after they handle messages sent to them, returns the first error of the tasks
+    async fn stop_accounts(&mut self) -> anyhow::Result<()> {
+        for (_, acc_sender) in self.accounts_channels.drain() {
+            let _ = acc_sender.send(Message::Stop).await; // failed task is stopped already
+        }
+        let mut res = Ok(());
+        for task in self.tasks.drain(..) {
+            let task_res = task.await?;
+            if res.is_ok() {
+                res = task_res;
             }
         }
+        res
     }
 
     /// Process transaction:
     ///
-    /// if there are no workers for account, open new connection,
-    ///
-    /// if there are workers for account, send transaction,
+    /// check transaction id against ids of all accounts,
     ///
-    /// if connection is closed, remove worker,
+    /// send transaction to the account, or send it as rejected if id check failed
     pub async fn process_tx(&mut self, tx: Transaction) -> anyhow::Result<()> {
         let acc_id = tx.account();
+        self.seq = self.seq.max(tx.seq());
+        let message = match self.tx_ids.register(&tx).await {
+            Ok(()) => Message::Tx(tx),
+            Err(reason) => Message::Rejected(tx, reason),
+        };
+        self.send_to_account(acc_id, message).await
+    }
+
+    /// Send message to the account task:
+    ///
+    /// if there are no workers for account, open new connection,
+    ///
+    /// if there are workers for account, send message,
+    ///
+    /// if the task failed, e.g. on WAL or store error, its channel is closed and the error is returned,
+    /// the error of the task itself is returned by `stop_accounts`
+    async fn send_to_account(&mut self, acc_id: AccountID, message: Message) -> anyhow::Result<()> {
         //if there are task for account
         if let Some(acc_sender) = self.accounts_channels.get_mut(&acc_id) {
-            // send transaction to the task
-            if acc_sender.send(Message::Tx(tx.clone())).await.is_err() {
-                self.accounts_channels.remove(&acc_id);
-                // TODO: make proper connection reopen
+            // send transaction to the task, it is closed only if it failed
+            if acc_sender.send(message).await.is_err() {
+                return Err(anyhow!("task of account {} stopped", acc_id));
             }
         } else {
             // create new task for account
 
             // get or create account 'acc_id'
-            let account = self
-                .accounts
-                .entry(acc_id)
-                .or_insert(Arc::new(Mutex::new(Account::new(acc_id))));
+            if !self.accounts.contains_key(&acc_id) {
+                let account = self.take_account(acc_id)?;
+                self.accounts.insert(acc_id, Arc::new(Mutex::new(account)));
+            }
+            let account = &self.accounts[&acc_id];
             let account = Arc::clone(account);
 
             // open new channel
             let (acc_sender, mut acc_receiver) = mpsc::channel(CHANNEL_BUUFER_SIZE);
-            acc_sender.send(Message::Tx(tx)).await?;
+            acc_sender.send(message).await?;
             self.accounts_channels.insert(acc_id, acc_sender);
 
             // spawn new task for account processing
-            tokio::spawn(async move {
-                while let Some(tx_msg) = acc_receiver.recv().await {
-                    let mut lock = account.lock().await;
-                    match tx_msg {
-                        Message::Tx(tx) => {
-                            lock.process(&tx).await;
+            let wal = self.wal.clone();
+            let tx_ids = Arc::clone(&self.tx_ids);
+            let task = tokio::spawn(async move {
+                let res = async {
+                    while let Some(tx_msg) = acc_receiver.recv().await {
+                        let mut lock = account.lock().await;
+                        let record = match tx_msg {
+                            Message::Tx(tx) => match lock.try_process(&tx) {
+                                Ok(res) => WalRecord {
+                                    rejected: res.err(),
+                                    tx,
+                                    by_service: false,
+                                },
+                                Err(err) => {
+                                    // claim is resolved not to block the service waiting for it
+                                    tx_ids.resolve(&tx, false);
+                                    return Err(err);
+                                }
+                            },
+                            Message::Rejected(tx, reason) => {
+                                lock.reject(&tx, reason)?;
+                                WalRecord {
+                                    tx,
+                                    rejected: Some(reason),
+                                    by_service: true,
+                                }
+                            }
+                            Message::Stop => {
+                                break;
+                            }
+                        };
+                        // log before the next transaction is taken, and before other accounts can take the id
+                        let logged = match &wal {
+                            Some(wal) => wal.append(&record).context("failed to write WAL"),
+                            None => Ok(()),
+                        };
+                        // claim is resolved even if logging failed, not to block the service waiting for it
+                        if !record.by_service {
+                            tx_ids.resolve(&record.tx, record.rejected.is_none());
                         }
-                        Message::Stop => {
-                            break;
+                        logged?;
+                        // TODO: close connection by timeout: not relevant in 'read file' case
+                    }
+                    anyhow::Ok(())
+                }
+                .await;
+                if res.is_err() {
+                    // claims of transactions left in the channel are released,
+                    // so the service doesn't wait for them on ids referenced by other accounts
+                    acc_receiver.close();
+                    while let Ok(tx_msg) = acc_receiver.try_recv() {
+                        if let Message::Tx(tx) = tx_msg {
+                            tx_ids.resolve(&tx, false);
                         }
                     }
-                    // TODO: close connection by timeout: not relevant in 'read file' case
                 }
+                res
             });
+            self.tasks.push(task);
         }
         Ok(())
     }
 
-    /// Get all accounts  
+    /// Take account restored from snapshot, or create new one, and apply configuration of the service to it
+    ///
+    /// fails if history of the account can't be moved to the store of the service
+    fn take_account(&mut self, acc_id: AccountID) -> anyhow::Result<Account> {
+        let overdraft_limit = self
+            .overdraft_limits
+            .get(&acc_id)
+            .copied()
+            .unwrap_or_default();
+        let account = self
+            .restored
+            .remove(&acc_id)
+            .unwrap_or_else(|| Account::new(acc_id))
+            .set_overdraft_limit(overdraft_limit)
+            .set_dispute_policy(self.dispute_policy)
+            .set_statement(self.statement_clients.contains(&acc_id))
+            .set_tx_store(self.store.store(acc_id))?;
+        Ok(match &self.budget {
+            Some(budget) => account.set_memory_budget(budget.clone()),
+            None => account,
+        })
+    }
+
+    /// Get all accounts ordered by id
     ///
     /// clones all accounts states and returns them without mutexes
-    pub async fn get_accounts(&mut self) -> HashMap<AccountID, Account> {
-        let mut res = HashMap::with_capacity(self.accounts.len());
+    pub async fn get_accounts(&mut self) -> BTreeMap<AccountID, Account> {
+        let mut res = BTreeMap::new();
         for (&id, m) in &self.accounts {
             let val = m.lock().await;
             res.insert(id, val.clone());
         }
+        for (&id, account) in &self.restored {
+            res.insert(id, account.clone());
+        }
         res
     }
+
+    /// Rebuild balances of the account as they were after the transaction with sequence number `seq`
+    ///
+    /// none if there is no such account, or its events up to a later sequence number are folded
+    pub async fn balance_at(&self, acc_id: AccountID, seq: u64) -> Option<Balance> {
+        match self.accounts.get(&acc_id) {
+            Some(account) => account.lock().await.balance_at(seq),
+            None => self
+                .restored
+                .get(&acc_id)
+                .and_then(|account| account.balance_at(seq)),
+        }
+    }
+
+    /// Fold event logs of all accounts up to sequence number `seq`, see `Account::compact_events`
+    pub async fn compact_events(&mut self, seq: u64) {
+        for account in self.accounts.values() {
+            account.lock().await.compact_events(seq);
+        }
+        for account in self.restored.values_mut() {
+            account.compact_events(seq);
+        }
+    }
+
+    /// Get state of all accounts and transaction ids to continue from it in the next run
+    ///
+    /// fails if history of an account can't be read
+    pub async fn snapshot(&mut self) -> anyhow::Result<Snapshot> {
+        let accounts = self
+            .get_accounts()
+            .await
+            .values()
+            .map(Account::state)
+            .collect::<anyhow::Result<_>>()?;
+        Ok(Snapshot::new(self.seq, accounts, self.tx_ids.owners()))
+    }
+}
+
+/// Owners of deposit and withdrawal ids, shared by the service and account tasks
+#[derive(Debug, Default)]
+struct TxIds {
+    claims: std::sync::Mutex<Claims>,
+    resolved: Notify, // some claim got result from its account
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Claim {
+    owner: AccountID,
+    pending: usize, // deposits and withdrawals with this id sent to the owner and not processed yet
+    applied: bool,  // owner applied one of them
+}
+
+#[derive(Debug, Default)]
+struct Claims {
+    open: HashMap<TxID, Claim>, // claims with pending transactions, or all claims if they are not compact
+    settled: Option<Owners>, // applied claims without pending transactions, if they are kept compact
+}
+
+impl Claims {
+    fn get(&self, id: TxID) -> Option<Claim> {
+        let settled = || {
+            let owner = self.settled.as_ref()?.get(id)?;
+            Some(Claim {
+                owner,
+                pending: 0,
+                applied: true,
+            })
+        };
+        self.open.get(&id).copied().or_else(settled)
+    }
+
+    /// Move claim out of the map if it is applied and there is nothing pending
+    fn settle(&mut self, id: TxID) {
+        if let (Some(settled), Some(claim)) = (&mut self.settled, self.open.get(&id)) {
+            if claim.applied && claim.pending == 0 {
+                settled.insert(id, claim.owner);
+                self.open.remove(&id);
+            }
+        }
+    }
+}
+
+/// Owners of ids kept as bitmaps: one of all ids, and one for every bit of account id with ids of owners having it,
+/// so id takes a few bits instead of a map entry
+#[derive(Debug, Default)]
+struct Owners {
+    ids: RoaringBitmap,
+    bits: [RoaringBitmap; AccountID::BITS as usize],
+}
+
+impl Owners {
+    fn insert(&mut self, id: TxID, owner: AccountID) {
+        self.ids.insert(id);
+        for (bit, ids) in self.bits.iter_mut().enumerate() {
+            if owner & (1 << bit) != 0 {
+                ids.insert(id);
+            } else {
+                ids.remove(id);
+            }
+        }
+    }
+
+    fn get(&self, id: TxID) -> Option<AccountID> {
+        self.ids.contains(id).then(|| {
+            self.bits
+                .iter()
+                .enumerate()
+                .filter(|(_, ids)| ids.contains(id))
+                .map(|(bit, _)| 1 << bit)
+                .sum()
+        })
+    }
+}
+
+impl From<HashMap<TxID, AccountID>> for TxIds {
+    fn from(owners: HashMap<TxID, AccountID>) -> Self {
+        let open = owners
+            .into_iter()
+            .map(|(id, owner)| {
+                let claim = Claim {
+                    owner,
+                    pending: 0,
+                    applied: true,
+                };
+                (id, claim)
+            })
+            .collect();
+        Self {
+            claims: std::sync::Mutex::new(Claims {
+                open,
+                settled: None,
+            }),
+            resolved: Notify::new(),
+        }
+    }
+}
+
+impl TxIds {
+    /// Check that transaction id is globally unique:
+    ///
+    /// deposit and withdrawal should have id which is not used by any account, id is claimed for the account,
+    /// and released if the account rejects the transaction,
+    ///
+    /// dispute, resolve and chargeback should not refer to id registered for another account,
+    ///
+    /// administrative transactions are not checked;
+    /// if id is claimed by another account which didn't process it yet, waits for the result
+    async fn register(&self, tx: &Transaction) -> Result<(), RejectionReason> {
+        loop {
+            let resolved = self.resolved.notified();
+            tokio::pin!(resolved);
+            resolved.as_mut().enable(); // don't miss claim resolved after the check
+            if let Some(res) = self.try_claim(tx) {
+                return res;
+            }
+            resolved.await;
+        }
+    }
+
+    /// Check id of transaction and claim it for deposit or withdrawal, see `register`
+    ///
+    /// returns none if the result depends on transaction of another account which is not processed yet
+    fn try_claim(&self, tx: &Transaction) -> Option<Result<(), RejectionReason>> {
+        let mut claims = self.claims.lock().expect("tx ids lock poisoned");
+        let res = match (tx.tx_type(), claims.get(tx.id())) {
+            (tx_type, _) if tx_type.is_admin() => Ok(()),
+            (TransactionType::Deposit | TransactionType::Withdrawal, None) => {
+                let claim = Claim {
+                    owner: tx.account(),
+                    pending: 1,
+                    applied: false,
+                };
+                claims.open.insert(tx.id(), claim);
+                Ok(())
+            }
+            (_, Some(claim)) if claim.owner != tx.account() && !claim.applied => return None,
+            (TransactionType::Deposit | TransactionType::Withdrawal, Some(claim)) => {
+                if claim.owner == tx.account() {
+                    let claim = Claim {
+                        pending: claim.pending + 1,
+                        ..claim
+                    };
+                    claims.open.insert(tx.id(), claim);
+                    Ok(()) // account rejects it as duplicate itself
+                } else {
+                    Err(RejectionReason::DuplicateTransaction)
+                }
+            }
+            (_, Some(claim)) if claim.owner != tx.account() => {
+                Err(RejectionReason::CrossClientReference)
+            }
+            _ => Ok(()),
+        };
+        Some(res)
+    }
+
+    /// Keep claim of deposit or withdrawal applied by the account, release it if nothing was applied
+    fn resolve(&self, tx: &Transaction, applied: bool) {
+        if !matches!(
+            tx.tx_type(),
+            TransactionType::Deposit | TransactionType::Withdrawal
+        ) {
+            return;
+        }
+        let mut claims = self.claims.lock().expect("tx ids lock poisoned");
+        if let Some(claim) = claims.open.get_mut(&tx.id()) {
+            claim.pending -= 1;
+            claim.applied |= applied;
+            if claim.pending == 0 && !claim.applied {
+                claims.open.remove(&tx.id());
+            }
+        }
+        claims.settle(tx.id());
+        self.resolved.notify_waiters();
+    }
+
+    /// Keep applied claims without pending transactions compact from now on, for memory budget
+    fn compact(&self) {
+        let mut claims = self.claims.lock().expect("tx ids lock poisoned");
+        claims.settled.get_or_insert_with(Owners::default);
+        let ids = claims.open.keys().copied().collect::<Vec<_>>();
+        for id in ids {
+            claims.settle(id);
+        }
+    }
+
+    /// Owners of applied deposits and withdrawals
+    fn owners(&self) -> HashMap<TxID, AccountID> {
+        let claims = self.claims.lock().expect("tx ids lock poisoned");
+        let open = claims
+            .open
+            .iter()
+            .filter(|(_, claim)| claim.applied)
+            .map(|(&id, claim)| (id, claim.owner));
+        let settled = claims.settled.iter().flat_map(|settled| {
+            settled
+                .ids
+                .iter()
+                .filter(|&id| !claims.open.contains_key(&id))
+                .filter_map(|id| Some((id, settled.get(id)?)))
+        });
+        open.chain(settled).collect()
+    }
 }
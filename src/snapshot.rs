@@ -0,0 +1,71 @@
+use crate::account::AccountState;
+use crate::primitives::{AccountID, TxID};
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::{fs, io, path::Path};
+
+/// Version of the snapshot format, it is bumped whenever the format changes:
+///
+/// 2 adds checkpoints of folded events and ids of expired lifecycles, which older binaries would drop
+pub const SNAPSHOT_VERSION: u32 = 2;
+
+/// Oldest version which is still loaded, fields added later get defaults
+pub const MIN_SNAPSHOT_VERSION: u32 = 1;
+
+/// State of the service after a run, to continue processing on top of it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub version: u32,
+    pub seq: u64, // sequence number of the last processed transaction
+    pub accounts: Vec<AccountState>,
+    pub tx_ids: HashMap<TxID, AccountID>, // owner account of every deposit and withdrawal id
+}
+
+impl Snapshot {
+    pub fn new(seq: u64, accounts: Vec<AccountState>, tx_ids: HashMap<TxID, AccountID>) -> Self {
+        Self {
+            version: SNAPSHOT_VERSION,
+            seq,
+            accounts,
+            tx_ids,
+        }
+    }
+
+    /// Write snapshot to a temporary file and move it to `path`, so existing snapshot is not lost on failure
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        let tmp = path.with_extension("tmp");
+        let mut file = io::BufWriter::new(fs::File::create(&tmp)?);
+        serde_json::to_writer(&mut file, self)?;
+        io::Write::flush(&mut file)?;
+        file.get_ref().sync_all()?;
+        fs::rename(tmp, path)?;
+        Ok(())
+    }
+
+    /// Load snapshot, version is checked before the rest of the snapshot is parsed
+    ///
+    /// snapshots of older versions are loaded, newer versions and versions before `MIN_SNAPSHOT_VERSION` are rejected
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        #[derive(Deserialize)]
+        struct Version {
+            version: u32,
+        }
+
+        let data = fs::read(path)?;
+        let Version { version } = serde_json::from_slice(&data)?;
+        if !(MIN_SNAPSHOT_VERSION..=SNAPSHOT_VERSION).contains(&version) {
+            return Err(anyhow!(
+                "Unsupported snapshot version {}, expected {} to {}",
+                version,
+                MIN_SNAPSHOT_VERSION,
+                SNAPSHOT_VERSION
+            ));
+        }
+        let snapshot: Self = serde_json::from_slice(&data)?;
+        Ok(Self {
+            version: SNAPSHOT_VERSION, // upgraded by defaults of new fields
+            ..snapshot
+        })
+    }
+}
@@ -0,0 +1,335 @@
+use crate::account::TxLifecycle;
+use crate::primitives::{AccountID, TxID};
+use crate::transaction::{RejectionReason, Transaction};
+use roaring::RoaringBitmap;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::path::Path;
+
+/// Transaction history of one account: lifecycles of deposits and withdrawals by id and failed transactions
+///
+/// every method fails if the history can't be read or written
+pub trait TxStore: fmt::Debug + Send {
+    /// Get lifecycle of deposit or withdrawal `id`, borrowed if the store keeps it in memory
+    fn get(&self, id: TxID) -> anyhow::Result<Option<Cow<'_, TxLifecycle>>>;
+
+    /// Insert lifecycle of new deposit or withdrawal
+    fn put(&mut self, id: TxID, lifecycle: TxLifecycle) -> anyhow::Result<()>;
+
+    /// Change lifecycle `id` with `f` after dispute, resolve or chargeback, nothing is done if there is no such lifecycle
+    fn update(&mut self, id: TxID, f: &mut dyn FnMut(&mut TxLifecycle)) -> anyhow::Result<()>;
+
+    /// Replace lifecycle with its id, after which it can't be disputed
+    fn expire(&mut self, id: TxID) -> anyhow::Result<()>;
+
+    /// Check if lifecycle `id` is replaced with its id
+    fn is_expired(&self, id: TxID) -> anyhow::Result<bool>;
+
+    /// Visit every lifecycle in place for compaction, `f` tells if it changed the lifecycle or if it is expired
+    fn retain(&mut self, f: &mut dyn FnMut(TxID, &mut TxLifecycle) -> Retain)
+        -> anyhow::Result<()>;
+
+    /// Append transaction which was not applied to the account
+    fn record_failure(&mut self, tx: Transaction, reason: RejectionReason) -> anyhow::Result<()>;
+
+    /// Get all lifecycles, for snapshots
+    fn lifecycles(&self) -> anyhow::Result<HashMap<TxID, TxLifecycle>>;
+
+    /// Get ids of expired lifecycles, for snapshots
+    fn expired(&self) -> anyhow::Result<HashSet<TxID>>;
+
+    /// Get failed transactions in order of failure, borrowed if the store keeps them in memory
+    fn failed(&self) -> anyhow::Result<Cow<'_, [(Transaction, RejectionReason)]>>;
+
+    fn clone_box(&self) -> Box<dyn TxStore>;
+}
+
+/// What compaction did with the lifecycle visited by `TxStore::retain`
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum Retain {
+    Unchanged,
+    Changed,
+    Expired, // lifecycle is replaced with its id
+}
+
+impl Clone for Box<dyn TxStore> {
+    fn clone(&self) -> Self {
+        self.clone_box()
+    }
+}
+
+/// History kept in memory for the whole run
+#[derive(Debug, Clone, Default)]
+pub struct MemoryStore {
+    txs: HashMap<TxID, TxLifecycle>,
+    expired: RoaringBitmap, // compressed set of ids, as most lifecycles expire on long inputs
+    failed: Vec<(Transaction, RejectionReason)>,
+}
+
+impl MemoryStore {
+    /// Store with history of restored account, ids in `expired` must not be in `txs`
+    pub fn new(
+        txs: HashMap<TxID, TxLifecycle>,
+        expired: HashSet<TxID>,
+        failed: Vec<(Transaction, RejectionReason)>,
+    ) -> Self {
+        Self {
+            txs,
+            expired: expired.into_iter().collect(),
+            failed,
+        }
+    }
+}
+
+impl TxStore for MemoryStore {
+    fn get(&self, id: TxID) -> anyhow::Result<Option<Cow<'_, TxLifecycle>>> {
+        Ok(self.txs.get(&id).map(Cow::Borrowed))
+    }
+
+    fn put(&mut self, id: TxID, lifecycle: TxLifecycle) -> anyhow::Result<()> {
+        self.txs.insert(id, lifecycle);
+        Ok(())
+    }
+
+    fn update(&mut self, id: TxID, f: &mut dyn FnMut(&mut TxLifecycle)) -> anyhow::Result<()> {
+        if let Some(lifecycle) = self.txs.get_mut(&id) {
+            f(lifecycle);
+        }
+        Ok(())
+    }
+
+    fn expire(&mut self, id: TxID) -> anyhow::Result<()> {
+        self.txs.remove(&id);
+        self.expired.insert(id);
+        Ok(())
+    }
+
+    fn is_expired(&self, id: TxID) -> anyhow::Result<bool> {
+        Ok(self.expired.contains(id))
+    }
+
+    fn retain(
+        &mut self,
+        f: &mut dyn FnMut(TxID, &mut TxLifecycle) -> Retain,
+    ) -> anyhow::Result<()> {
+        let expired = &mut self.expired;
+        self.txs.retain(|&id, lifecycle| {
+            if f(id, lifecycle) == Retain::Expired {
+                expired.insert(id);
+                return false;
+            }
+            true
+        });
+        Ok(())
+    }
+
+    fn record_failure(&mut self, tx: Transaction, reason: RejectionReason) -> anyhow::Result<()> {
+        self.failed.push((tx, reason));
+        Ok(())
+    }
+
+    fn lifecycles(&self) -> anyhow::Result<HashMap<TxID, TxLifecycle>> {
+        Ok(self.txs.clone())
+    }
+
+    fn expired(&self) -> anyhow::Result<HashSet<TxID>> {
+        Ok(self.expired.iter().collect())
+    }
+
+    fn failed(&self) -> anyhow::Result<Cow<'_, [(Transaction, RejectionReason)]>> {
+        Ok(Cow::Borrowed(&self.failed))
+    }
+
+    fn clone_box(&self) -> Box<dyn TxStore> {
+        Box::new(self.clone())
+    }
+}
+
+/// History kept in sled database on disk, keys of every tree are prefixed with the client id
+///
+/// clones of the store are handles of the same history, not copies of it:
+/// cloned accounts see the latest history, and changes made through a clone are seen by the original
+#[derive(Debug, Clone)]
+pub struct SledStore {
+    client: AccountID,
+    txs: sled::Tree,     // lifecycle by client and tx id
+    expired: sled::Tree, // empty value by client and tx id
+    failed: sled::Tree,  // failed transaction by client and number of failure
+}
+
+impl SledStore {
+    fn key(&self, id: impl AsRef<[u8]>) -> Vec<u8> {
+        [&self.client.to_be_bytes(), id.as_ref()].concat()
+    }
+
+    fn values<T: serde::de::DeserializeOwned>(
+        &self,
+        tree: &sled::Tree,
+    ) -> impl Iterator<Item = anyhow::Result<(sled::IVec, T)>> {
+        tree.scan_prefix(self.client.to_be_bytes()).map(|entry| {
+            let (key, value) = entry?;
+            Ok((key, serde_json::from_slice(&value)?))
+        })
+    }
+
+    /// Number of the next failure, after the last one in the tree, so clones don't overwrite failures of each other
+    fn next_failure(&self) -> anyhow::Result<u64> {
+        let last = self
+            .failed
+            .scan_prefix(self.client.to_be_bytes())
+            .keys()
+            .next_back()
+            .transpose()?;
+        Ok(last.map_or(0, |key| {
+            let number = key[key.len() - 8..]
+                .try_into()
+                .expect("key ends with number of failure");
+            u64::from_be_bytes(number) + 1
+        }))
+    }
+}
+
+fn tx_id(key: &[u8]) -> TxID {
+    let id = key[key.len() - 4..]
+        .try_into()
+        .expect("key ends with tx id");
+    TxID::from_be_bytes(id)
+}
+
+impl TxStore for SledStore {
+    fn get(&self, id: TxID) -> anyhow::Result<Option<Cow<'_, TxLifecycle>>> {
+        match self.txs.get(self.key(id.to_be_bytes()))? {
+            Some(value) => Ok(Some(Cow::Owned(serde_json::from_slice(&value)?))),
+            None => Ok(None),
+        }
+    }
+
+    fn put(&mut self, id: TxID, lifecycle: TxLifecycle) -> anyhow::Result<()> {
+        let value = serde_json::to_vec(&lifecycle)?;
+        self.txs.insert(self.key(id.to_be_bytes()), value)?;
+        Ok(())
+    }
+
+    fn update(&mut self, id: TxID, f: &mut dyn FnMut(&mut TxLifecycle)) -> anyhow::Result<()> {
+        if let Some(lifecycle) = self.get(id)? {
+            let mut lifecycle = lifecycle.into_owned();
+            f(&mut lifecycle);
+            self.put(id, lifecycle)?;
+        }
+        Ok(())
+    }
+
+    fn expire(&mut self, id: TxID) -> anyhow::Result<()> {
+        let key = self.key(id.to_be_bytes());
+        self.txs.remove(&key)?;
+        self.expired.insert(key, &[])?;
+        Ok(())
+    }
+
+    fn is_expired(&self, id: TxID) -> anyhow::Result<bool> {
+        Ok(self.expired.contains_key(self.key(id.to_be_bytes()))?)
+    }
+
+    fn retain(
+        &mut self,
+        f: &mut dyn FnMut(TxID, &mut TxLifecycle) -> Retain,
+    ) -> anyhow::Result<()> {
+        // only visited lifecycle is deserialized, and only changed one is written back
+        for entry in self.values::<TxLifecycle>(&self.txs) {
+            let (key, mut lifecycle) = entry?;
+            match f(tx_id(&key), &mut lifecycle) {
+                Retain::Unchanged => {}
+                Retain::Changed => {
+                    self.txs.insert(key, serde_json::to_vec(&lifecycle)?)?;
+                }
+                Retain::Expired => {
+                    self.txs.remove(&key)?;
+                    self.expired.insert(key, &[])?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn record_failure(&mut self, tx: Transaction, reason: RejectionReason) -> anyhow::Result<()> {
+        let value = serde_json::to_vec(&(tx, reason))?;
+        let number = self.next_failure()?;
+        self.failed.insert(self.key(number.to_be_bytes()), value)?;
+        Ok(())
+    }
+
+    fn lifecycles(&self) -> anyhow::Result<HashMap<TxID, TxLifecycle>> {
+        self.values(&self.txs)
+            .map(|entry| entry.map(|(key, lifecycle)| (tx_id(&key), lifecycle)))
+            .collect()
+    }
+
+    fn expired(&self) -> anyhow::Result<HashSet<TxID>> {
+        self.expired
+            .scan_prefix(self.client.to_be_bytes())
+            .keys()
+            .map(|key| Ok(tx_id(&key?)))
+            .collect()
+    }
+
+    fn failed(&self) -> anyhow::Result<Cow<'_, [(Transaction, RejectionReason)]>> {
+        // big-endian numbers keep failures in order
+        let failed = self
+            .values(&self.failed)
+            .map(|entry| entry.map(|(_, failed)| failed))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        Ok(Cow::Owned(failed))
+    }
+
+    fn clone_box(&self) -> Box<dyn TxStore> {
+        Box::new(self.clone())
+    }
+}
+
+/// Where accounts keep their transaction history, chosen at runtime
+#[derive(Debug, Clone, Default)]
+pub enum StoreBackend {
+    #[default]
+    Memory,
+    Sled {
+        txs: sled::Tree,
+        expired: sled::Tree,
+        failed: sled::Tree,
+    },
+}
+
+impl StoreBackend {
+    /// Open sled database at `path`, history left in it by previous runs is removed
+    pub fn sled(path: &Path) -> anyhow::Result<Self> {
+        let db = sled::open(path)?;
+        let txs = db.open_tree("txs")?;
+        let expired = db.open_tree("expired")?;
+        let failed = db.open_tree("failed")?;
+        for tree in [&txs, &expired, &failed] {
+            tree.clear()?;
+        }
+        Ok(Self::Sled {
+            txs,
+            expired,
+            failed,
+        })
+    }
+
+    /// Store of account `client`, it is filled by `Account::set_tx_store`
+    pub fn store(&self, client: AccountID) -> Box<dyn TxStore> {
+        match self {
+            StoreBackend::Memory => Box::new(MemoryStore::default()),
+            StoreBackend::Sled {
+                txs,
+                expired,
+                failed,
+            } => Box::new(SledStore {
+                client,
+                txs: txs.clone(),
+                expired: expired.clone(),
+                failed: failed.clone(),
+            }),
+        }
+    }
+}
@@ -1,6 +1,8 @@
 use crate::primitives::{AccountID, Coin, TxID, PRECISION};
 use anyhow::{anyhow, Error as AnyhowError, Result as AnyhowResult};
 use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::sync::Arc;
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct InputTransaction {
@@ -10,15 +12,75 @@ pub struct InputTransaction {
     #[serde(rename = "tx")]
     pub id: String,
     pub amount: Option<String>,
+    pub timestamp: Option<String>, // optional column, unix time in seconds
 }
 
-#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+impl InputTransaction {
+    /// Names of the input fields, in the default column order
+    pub const FIELDS: [&'static str; 5] = ["type", "client", "tx", "amount", "timestamp"];
+}
+
+/// Transaction as a JSON object, fields can be strings or numbers
+#[derive(Debug, Deserialize, Clone)]
+pub struct JsonInputTransaction {
+    #[serde(rename = "type")]
+    pub tx_type: String,
+    pub client: JsonField,
+    #[serde(rename = "tx")]
+    pub id: JsonField,
+    pub amount: Option<JsonField>,
+    pub timestamp: Option<JsonField>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(untagged)]
+pub enum JsonField {
+    String(String),
+    Number(serde_json::Number), // written back as it is in the input, with `arbitrary_precision`
+}
+
+impl From<JsonField> for String {
+    fn from(field: JsonField) -> Self {
+        match field {
+            JsonField::String(s) => s,
+            JsonField::Number(n) => n.to_string(),
+        }
+    }
+}
+
+impl From<JsonInputTransaction> for InputTransaction {
+    fn from(input: JsonInputTransaction) -> Self {
+        Self {
+            tx_type: input.tx_type,
+            client: input.client.into(),
+            id: input.id.into(),
+            amount: input.amount.map(String::from),
+            timestamp: input.timestamp.map(String::from),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum TransactionType {
     Deposit,
     Withdrawal,
     Dispute,
     Resolve,
     Chargeback,
+    Unlock, // administrative: unlock account locked by chargeback or freeze
+    Freeze, // administrative: lock account
+    Close,  // administrative: lock account permanently
+}
+
+impl TransactionType {
+    /// check if transaction changes account state instead of funds
+    pub fn is_admin(&self) -> bool {
+        matches!(
+            self,
+            TransactionType::Unlock | TransactionType::Freeze | TransactionType::Close
+        )
+    }
 }
 
 impl TryFrom<String> for TransactionType {
@@ -31,35 +93,54 @@ impl TryFrom<String> for TransactionType {
             "dispute" => Ok(TransactionType::Dispute),
             "resolve" => Ok(TransactionType::Resolve),
             "chargeback" => Ok(TransactionType::Chargeback),
+            "unlock" => Ok(TransactionType::Unlock),
+            "freeze" => Ok(TransactionType::Freeze),
+            "close" => Ok(TransactionType::Close),
             _ => Err(anyhow!("Unknown TransactionType: {}", input)),
         }
     }
 }
 
-#[derive(Debug, Clone, Eq, PartialEq)]
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
 pub struct Transaction {
     tx_type: TransactionType,
     account: AccountID,
     id: TxID,
     amount: Option<Coin>,
+    line: u64,              // line of the transaction in the input, 0 if unknown
+    seq: u64,               // sequence number of the row in the input, 0 if unknown
+    timestamp: Option<u64>, // unix time in seconds, if given in the input
+    #[serde(default)] // not saved by older snapshots
+    input: Option<Arc<str>>, // name of the input, if there are several inputs
 }
 
 impl TryFrom<InputTransaction> for Transaction {
     type Error = AnyhowError;
 
     fn try_from(input: InputTransaction) -> AnyhowResult<Self> {
+        Self::parse(input, false)
+    }
+}
+
+impl Transaction {
+    /// Build transaction from input, but fail on amounts with more than `PRECISION` decimal places
+    /// instead of rounding them
+    pub fn try_from_strict(input: InputTransaction) -> AnyhowResult<Self> {
+        Self::parse(input, true)
+    }
+
+    fn parse(input: InputTransaction, strict: bool) -> AnyhowResult<Self> {
         let tx_type = input.tx_type.try_into()?;
         let amount = match tx_type {
             TransactionType::Deposit | TransactionType::Withdrawal => {
-                let val: Coin = input
-                    .amount
-                    .ok_or(anyhow!("Wrong amount"))?
-                    .trim()
-                    .parse()?;
-                if val < Coin::new(0, 0) {
-                    return Err(anyhow!("Negative amount"));
+                Some(parse_amount(input.amount.as_deref(), strict)?)
+            }
+            TransactionType::Dispute | TransactionType::Resolve | TransactionType::Chargeback => {
+                // partial dispute, resolve or chargeback if amount is given
+                match input.amount.as_deref().map(str::trim) {
+                    None | Some("") => None,
+                    amount => Some(parse_amount(amount, strict)?),
                 }
-                Some(val.round_dp(PRECISION))
             }
             _ => None,
         };
@@ -69,14 +150,83 @@ impl TryFrom<InputTransaction> for Transaction {
             account: input.client.trim().parse()?,
             id: input.id.trim().parse()?,
             amount,
+            line: 0,
+            seq: 0,
+            timestamp: match input.timestamp.as_deref().map(str::trim) {
+                None | Some("") => None,
+                Some(timestamp) => Some(timestamp.parse()?),
+            },
+            input: None,
         })
     }
 }
 
-#[derive(PartialEq)]
+/// Parse non-negative amount, rounding it to `PRECISION` or failing on more decimal places if `strict`
+fn parse_amount(amount: Option<&str>, strict: bool) -> AnyhowResult<Coin> {
+    let val: Coin = amount.ok_or(anyhow!("Wrong amount"))?.trim().parse()?;
+    if val < Coin::new(0, 0) {
+        return Err(anyhow!("Negative amount"));
+    }
+    if strict && val.normalize().scale() > PRECISION {
+        return Err(anyhow!(
+            "Amount {} has more than {} decimal places",
+            val,
+            PRECISION
+        ));
+    }
+    Ok(val.round_dp(PRECISION))
+}
+
+#[derive(Debug, PartialEq)]
 pub enum AncestorState {
-    Valid,   // transaction can be parent for current
-    Invalid, // transaction doesn't allow current transaction to occur
+    Valid,                    // transaction can be parent for current
+    Invalid(RejectionReason), // transaction doesn't allow current transaction to occur
+}
+
+/// Reason why transaction was not applied to the account
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RejectionReason {
+    AccountLocked,            // no transactions are applied to locked account
+    DuplicateTransaction,     // deposit or withdrawal with tx id which is already used
+    MissingParent,            // dispute, resolve or chargeback for unknown tx id
+    AlreadyDisputed,          // dispute for transaction which is under dispute already
+    NotDisputed,              // resolve or chargeback for transaction which is not under dispute
+    ParentMismatch,           // parent transaction belongs to another client or tx id
+    CrossClientReference,     // dispute, resolve or chargeback for tx id of another client
+    InsufficientFunds,        // withdrawal would take available funds below the overdraft limit
+    DisputeExceedsUndisputed, // dispute amount is larger than not disputed part of transaction
+    ExceedsDisputed,          // resolve or chargeback amount is larger than disputed part
+    AccountClosed,            // no transactions, including administrative, are applied
+    NotLocked,                // unlock of account which is not locked
+    DisputeWindowExpired,     // dispute comes too late after deposit or withdrawal
+    TooManyDisputes,          // transaction is disputed maximum allowed number of times already
+    MissingTimestamp, // dispute window is given in seconds, but dispute or its transaction has no timestamp
+}
+
+impl fmt::Display for RejectionReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let reason = match self {
+            RejectionReason::AccountLocked => "account is locked",
+            RejectionReason::DuplicateTransaction => "duplicate transaction id",
+            RejectionReason::MissingParent => "no deposit or withdrawal with this transaction id",
+            RejectionReason::AlreadyDisputed => "transaction is already disputed",
+            RejectionReason::NotDisputed => "transaction is not disputed",
+            RejectionReason::ParentMismatch => "parent transaction doesn't match",
+            RejectionReason::CrossClientReference => "transaction belongs to another client",
+            RejectionReason::InsufficientFunds => "insufficient funds",
+            RejectionReason::DisputeExceedsUndisputed => {
+                "dispute amount exceeds undisputed part of transaction"
+            }
+            RejectionReason::ExceedsDisputed => "amount exceeds disputed part of transaction",
+            RejectionReason::DisputeWindowExpired => "dispute window expired",
+            RejectionReason::TooManyDisputes => "transaction is disputed too many times",
+            RejectionReason::MissingTimestamp => "timestamp is missing for dispute window",
+            RejectionReason::AccountClosed => "account is closed",
+            RejectionReason::NotLocked => "account is not locked",
+        };
+        write!(f, "{}", reason)
+    }
 }
 
 impl Transaction {
@@ -88,6 +238,11 @@ impl Transaction {
         self.amount.unwrap_or_default()
     }
 
+    /// Amount as it was given in the input, `None` for transactions without amount
+    pub fn input_amount(&self) -> Option<Coin> {
+        self.amount
+    }
+
     pub fn tx_type(&self) -> TransactionType {
         self.tx_type
     }
@@ -96,38 +251,98 @@ impl Transaction {
         self.id
     }
 
+    pub fn line(&self) -> u64 {
+        self.line
+    }
+
+    /// Set line of the transaction in the input
+    pub fn with_line(self, line: u64) -> Self {
+        Self { line, ..self }
+    }
+
+    pub fn seq(&self) -> u64 {
+        self.seq
+    }
+
+    /// Set sequence number of the transaction in the input
+    pub fn with_seq(self, seq: u64) -> Self {
+        Self { seq, ..self }
+    }
+
+    pub fn timestamp(&self) -> Option<u64> {
+        self.timestamp
+    }
+
+    pub fn input(&self) -> Option<&str> {
+        self.input.as_deref()
+    }
+
+    /// Set name of the input of the transaction, line and sequence number are counted in it
+    pub fn with_input(self, input: Option<Arc<str>>) -> Self {
+        Self { input, ..self }
+    }
+
     pub fn valid_ancestor(&self, ancestor: &Self) -> AncestorState {
-        if self.account != ancestor.account {
-            return AncestorState::Invalid;
-        }
-        if self.id != ancestor.id {
-            return AncestorState::Invalid;
+        if self.account != ancestor.account || self.id != ancestor.id {
+            return AncestorState::Invalid(RejectionReason::ParentMismatch);
         }
+        self.valid_ancestor_type(ancestor.tx_type)
+    }
 
+    /// Same as `valid_ancestor` for ancestor of the same account and id, when only its type is kept
+    pub fn valid_ancestor_type(&self, ancestor: TransactionType) -> AncestorState {
         match self.tx_type {
             TransactionType::Deposit | TransactionType::Withdrawal => {
-                AncestorState::Invalid // we don't allow deposit and withdrawal if we have tx with same id + client
+                AncestorState::Invalid(RejectionReason::DuplicateTransaction) // we don't allow deposit and withdrawal if we have tx with same id + client
+            }
+            TransactionType::Unlock | TransactionType::Freeze | TransactionType::Close => {
+                AncestorState::Invalid(RejectionReason::ParentMismatch) // administrative transactions don't have parents, see valid_admin_ancestor
             }
             TransactionType::Dispute => {
-                match ancestor.tx_type {
+                match ancestor {
                     TransactionType::Deposit | TransactionType::Withdrawal => AncestorState::Valid, // we allow dispute for new tx
-                    TransactionType::Dispute => AncestorState::Invalid, // we don't allow second dispute after dispute
+                    TransactionType::Dispute => AncestorState::Valid, // we allow partial dispute after dispute, account checks that something is left to dispute
                     TransactionType::Resolve | TransactionType::Chargeback => AncestorState::Valid, // we allow second dispute for finalized dispute
+                    TransactionType::Unlock | TransactionType::Freeze | TransactionType::Close => {
+                        AncestorState::Invalid(RejectionReason::ParentMismatch)
+                    } // administrative transaction can't be parent
                 }
             }
             TransactionType::Resolve | TransactionType::Chargeback => {
-                match ancestor.tx_type {
+                match ancestor {
                     TransactionType::Deposit | TransactionType::Withdrawal => {
-                        AncestorState::Invalid
+                        AncestorState::Invalid(RejectionReason::NotDisputed)
                     } // we don't allow finalized dispute without dispute
                     TransactionType::Dispute => AncestorState::Valid, // we allow to finalize dispute
-                    TransactionType::Resolve | TransactionType::Chargeback => {
-                        AncestorState::Invalid
-                    } // we don't allow second dispute finalization
+                    TransactionType::Resolve | TransactionType::Chargeback => AncestorState::Valid, // we allow partial finalization after finalization, account checks that something is still disputed
+                    TransactionType::Unlock | TransactionType::Freeze | TransactionType::Close => {
+                        AncestorState::Invalid(RejectionReason::ParentMismatch)
+                    } // administrative transaction can't be parent
                 }
             }
         }
     }
+
+    /// Check administrative transaction against the last administrative transaction on account
+    /// and the account lock, which can also be set by chargeback
+    pub fn valid_admin_ancestor(&self, ancestor: Option<&Self>, locked: bool) -> AncestorState {
+        if ancestor.is_some_and(|ancestor| ancestor.tx_type == TransactionType::Close) {
+            return AncestorState::Invalid(RejectionReason::AccountClosed); // we don't allow anything after close
+        }
+
+        match self.tx_type {
+            TransactionType::Unlock if !locked => {
+                AncestorState::Invalid(RejectionReason::NotLocked) // we don't allow unlock of unlocked account
+            }
+            TransactionType::Freeze if locked => {
+                AncestorState::Invalid(RejectionReason::AccountLocked) // we don't allow second lock
+            }
+            TransactionType::Unlock | TransactionType::Freeze | TransactionType::Close => {
+                AncestorState::Valid
+            }
+            _ => AncestorState::Invalid(RejectionReason::ParentMismatch), // not administrative transaction
+        }
+    }
 }
 
 #[cfg(test)]
@@ -142,11 +357,44 @@ mod tests {
             client: "".to_owned(),
             id: "".to_owned(),
             amount: Some("".to_owned()),
+            timestamp: None,
         };
 
         assert!(Transaction::try_from(input).is_err());
     }
 
+    #[test]
+    fn test_json_input_transaction() {
+        let input: JsonInputTransaction = serde_json::from_str(
+            r#"{"type": " deposit", "client": 1, "tx": " 2 ", "amount": 1.5, "timestamp": null}"#,
+        )
+        .unwrap();
+        let tx = Transaction::try_from(InputTransaction::from(input)).unwrap();
+
+        assert_eq!(tx.tx_type(), TransactionType::Deposit);
+        assert_eq!(tx.account(), 1);
+        assert_eq!(tx.id(), 2);
+        assert_eq!(tx.amount(), Coin::new(15, 1));
+        assert_eq!(tx.timestamp(), None);
+
+        // numbers are read as written, without going through f64
+        let input: JsonInputTransaction = serde_json::from_str(
+            r#"{"type": "deposit", "client": 1, "tx": 2, "amount": 12345678901234.5678}"#,
+        )
+        .unwrap();
+        let tx = Transaction::try_from(InputTransaction::from(input)).unwrap();
+        assert_eq!(tx.amount(), "12345678901234.5678".parse().unwrap());
+
+        let input: JsonInputTransaction =
+            serde_json::from_str(r#"{"type": "dispute", "client": 1, "tx": 2}"#).unwrap();
+        assert_eq!(
+            Transaction::try_from(InputTransaction::from(input))
+                .unwrap()
+                .input_amount(),
+            None
+        );
+    }
+
     #[test]
     fn test_into_transaction_dispute() {
         let input = InputTransaction {
@@ -154,18 +402,51 @@ mod tests {
             client: "1".to_owned(),
             id: "2".to_owned(),
             amount: Some("3.0".to_owned()),
+            timestamp: None,
         };
 
         let output = Transaction {
             tx_type: TransactionType::Dispute,
             account: 1,
             id: 2,
-            amount: None,
+            amount: Some(Coin::new(3, 0)),
+            line: 0,
+            seq: 0,
+            timestamp: None,
+            input: None,
+        };
+
+        assert_eq!(Transaction::try_from(input).unwrap(), output);
+    }
+
+    #[test]
+    fn test_into_transaction_dispute_no_amount() {
+        let input = InputTransaction {
+            tx_type: "resolve".to_owned(),
+            client: "1".to_owned(),
+            id: "2".to_owned(),
+            amount: Some("  ".to_owned()),
+            timestamp: None,
         };
 
+        let output = tx(TransactionType::Resolve, 1, 2);
+
         assert_eq!(Transaction::try_from(input).unwrap(), output);
     }
 
+    #[test]
+    fn test_into_transaction_dispute_negative_amount() {
+        let input = InputTransaction {
+            tx_type: "chargeback".to_owned(),
+            client: "1".to_owned(),
+            id: "2".to_owned(),
+            amount: Some("-1".to_owned()),
+            timestamp: None,
+        };
+
+        assert!(Transaction::try_from(input).is_err());
+    }
+
     #[test]
     fn test_into_transaction_deposits() {
         let input = InputTransaction {
@@ -173,6 +454,7 @@ mod tests {
             client: "1".to_owned(),
             id: "2".to_owned(),
             amount: Some("3.0".to_owned()),
+            timestamp: None,
         };
 
         let output = Transaction {
@@ -180,6 +462,10 @@ mod tests {
             account: 1,
             id: 2,
             amount: Some(Coin::new(3, 0)),
+            line: 0,
+            seq: 0,
+            timestamp: None,
+            input: None,
         };
 
         assert_eq!(Transaction::try_from(input).unwrap(), output);
@@ -192,6 +478,7 @@ mod tests {
             client: "1 ".to_owned(),
             id: "2    ".to_owned(),
             amount: Some("    3.0".to_owned()),
+            timestamp: None,
         };
 
         let output = Transaction {
@@ -199,6 +486,10 @@ mod tests {
             account: 1,
             id: 2,
             amount: Some(Coin::new(3, 0)),
+            line: 0,
+            seq: 0,
+            timestamp: None,
+            input: None,
         };
 
         assert_eq!(Transaction::try_from(input).unwrap(), output);
@@ -211,6 +502,7 @@ mod tests {
             client: "1 ".to_owned(),
             id: "2    ".to_owned(),
             amount: Some("    ".to_owned()),
+            timestamp: None,
         };
 
         assert!(Transaction::try_from(input).is_err());
@@ -223,13 +515,151 @@ mod tests {
             client: "1 ".to_owned(),
             id: "2    ".to_owned(),
             amount: Some("-2.3".to_owned()),
+            timestamp: None,
         };
 
         assert!(Transaction::try_from(input).is_err());
     }
 
+    fn tx(tx_type: TransactionType, account: AccountID, id: TxID) -> Transaction {
+        Transaction {
+            tx_type,
+            account,
+            id,
+            amount: None,
+            line: 0,
+            seq: 0,
+            timestamp: None,
+            input: None,
+        }
+    }
+
+    #[test]
+    fn test_into_transaction_precision() {
+        let input = InputTransaction {
+            tx_type: "deposit".to_owned(),
+            client: "1".to_owned(),
+            id: "2".to_owned(),
+            amount: Some("1.23456".to_owned()),
+            timestamp: None,
+        };
+
+        assert_eq!(
+            Transaction::try_from(input.clone()).unwrap().amount(),
+            Coin::new(12346, 4)
+        );
+        assert!(Transaction::try_from_strict(input).is_err());
+    }
+
+    #[test]
+    fn test_into_transaction_strict_trailing_zeros() {
+        let input = InputTransaction {
+            tx_type: "withdrawal".to_owned(),
+            client: "1".to_owned(),
+            id: "2".to_owned(),
+            amount: Some("1.234500".to_owned()),
+            timestamp: None,
+        };
+
+        assert_eq!(
+            Transaction::try_from_strict(input).unwrap().amount(),
+            Coin::new(12345, 4)
+        );
+    }
+
     #[test]
     fn test_for_valid_ancestor() {
-        // TODO: add valid_ancestor tests
+        use TransactionType::*;
+
+        let deposit = tx(Deposit, 1, 1);
+        assert_eq!(
+            tx(Dispute, 1, 1).valid_ancestor(&deposit),
+            AncestorState::Valid
+        );
+        assert_eq!(
+            tx(Resolve, 1, 1).valid_ancestor(&tx(Dispute, 1, 1)),
+            AncestorState::Valid
+        );
+        assert_eq!(
+            tx(Dispute, 1, 1).valid_ancestor(&tx(Resolve, 1, 1)),
+            AncestorState::Valid
+        );
+        // partial disputes and finalizations, amounts are checked by account
+        assert_eq!(
+            tx(Dispute, 1, 1).valid_ancestor(&tx(Dispute, 1, 1)),
+            AncestorState::Valid
+        );
+        assert_eq!(
+            tx(Chargeback, 1, 1).valid_ancestor(&tx(Resolve, 1, 1)),
+            AncestorState::Valid
+        );
+    }
+
+    #[test]
+    fn test_for_valid_admin_ancestor() {
+        use TransactionType::*;
+
+        assert_eq!(
+            tx(Unlock, 1, 1).valid_admin_ancestor(None, true),
+            AncestorState::Valid
+        );
+        assert_eq!(
+            tx(Unlock, 1, 1).valid_admin_ancestor(None, false),
+            AncestorState::Invalid(RejectionReason::NotLocked)
+        );
+        assert_eq!(
+            tx(Freeze, 1, 2).valid_admin_ancestor(Some(&tx(Unlock, 1, 1)), false),
+            AncestorState::Valid
+        );
+        assert_eq!(
+            tx(Freeze, 1, 2).valid_admin_ancestor(Some(&tx(Freeze, 1, 1)), true),
+            AncestorState::Invalid(RejectionReason::AccountLocked)
+        );
+        assert_eq!(
+            tx(Close, 1, 2).valid_admin_ancestor(Some(&tx(Freeze, 1, 1)), true),
+            AncestorState::Valid
+        );
+        assert_eq!(
+            tx(Unlock, 1, 2).valid_admin_ancestor(Some(&tx(Close, 1, 1)), true),
+            AncestorState::Invalid(RejectionReason::AccountClosed)
+        );
+        assert_eq!(
+            tx(Deposit, 1, 2).valid_admin_ancestor(None, false),
+            AncestorState::Invalid(RejectionReason::ParentMismatch)
+        );
+    }
+
+    #[test]
+    fn test_into_transaction_admin() {
+        let input = InputTransaction {
+            tx_type: " Unlock ".to_owned(),
+            client: "1".to_owned(),
+            id: "2".to_owned(),
+            amount: Some("3.0".to_owned()),
+            timestamp: None,
+        };
+
+        let output = tx(TransactionType::Unlock, 1, 2);
+
+        assert_eq!(Transaction::try_from(input).unwrap(), output);
+    }
+
+    #[test]
+    fn test_for_invalid_ancestor_reason() {
+        use TransactionType::*;
+
+        let deposit = tx(Deposit, 1, 1);
+        assert_eq!(
+            tx(Withdrawal, 1, 1).valid_ancestor(&deposit),
+            AncestorState::Invalid(RejectionReason::DuplicateTransaction)
+        );
+        assert_eq!(
+            tx(Resolve, 1, 1).valid_ancestor(&deposit),
+            AncestorState::Invalid(RejectionReason::NotDisputed)
+        );
+        assert_eq!(
+            tx(Dispute, 2, 1).valid_ancestor(&deposit),
+            AncestorState::Invalid(RejectionReason::ParentMismatch)
+        );
     }
 }
@@ -0,0 +1,99 @@
+use crate::transaction::{RejectionReason, Transaction};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::io::{self, Write};
+use std::{fs, path::Path, sync::Mutex};
+
+/// Transaction handled by the account, with the reason if it was rejected
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WalRecord {
+    pub tx: Transaction,
+    pub rejected: Option<RejectionReason>, // none if transaction was applied
+    #[serde(default)]
+    pub by_service: bool, // rejected by id check of the service before reaching the account
+}
+
+/// Append-only log of handled transactions, one JSON record per line
+///
+/// every record is written to the file before the account task takes the next transaction
+/// and before id of the transaction is released for other accounts, so the log survives the process crash
+/// and every id check of the service is logged after transactions it depends on
+pub struct Wal {
+    file: Mutex<fs::File>,
+}
+
+impl Wal {
+    /// Open log for appending and read records which are already in it
+    ///
+    /// incomplete last record, left by crash in the middle of the write, is cut off
+    pub fn open(path: &Path) -> anyhow::Result<(Self, Vec<WalRecord>)> {
+        let mut file = fs::OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path)?;
+        let mut data = Vec::new();
+        io::Read::read_to_end(&mut file, &mut data)?;
+
+        let complete = data
+            .iter()
+            .rposition(|&b| b == b'\n')
+            .map_or(0, |pos| pos + 1);
+        if complete < data.len() {
+            file.set_len(complete as u64)?;
+        }
+        let records = data[..complete]
+            .split(|&b| b == b'\n')
+            .filter(|line| !line.is_empty())
+            .map(serde_json::from_slice)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok((
+            Self {
+                file: Mutex::new(file),
+            },
+            records,
+        ))
+    }
+
+    /// Append records to already open `file`, records which are in it are not read
+    pub fn from_file(file: fs::File) -> Self {
+        Self {
+            file: Mutex::new(file),
+        }
+    }
+
+    /// Write record with a single write, so records of different accounts are not mixed
+    pub fn append(&self, record: &WalRecord) -> anyhow::Result<()> {
+        let mut line = serde_json::to_vec(record)?;
+        line.push(b'\n');
+        let mut file = self.file.lock().expect("WAL lock poisoned");
+        file.write_all(&line)?;
+        Ok(())
+    }
+}
+
+/// Sequence numbers of transactions which are already processed, e.g. recovered from WAL
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Processed {
+    pub up_to: u64,          // all transactions up to this one are processed
+    pub after: HashSet<u64>, // processed transactions after `up_to`
+}
+
+impl Processed {
+    pub fn contains(&self, seq: u64) -> bool {
+        seq <= self.up_to || self.after.contains(&seq)
+    }
+}
+
+impl FromIterator<u64> for Processed {
+    /// Transactions are processed by accounts concurrently, so there can be gaps
+    fn from_iter<I: IntoIterator<Item = u64>>(seqs: I) -> Self {
+        let mut after = seqs.into_iter().collect::<HashSet<_>>();
+        let mut up_to = 0;
+        while after.remove(&(up_to + 1)) {
+            up_to += 1;
+        }
+        Self { up_to, after }
+    }
+}
@@ -1,22 +1,53 @@
 use krct_async::account::Account;
 use krct_async::primitives::*;
 use krct_async::service::Service;
-use krct_async::transaction::{InputTransaction, Transaction};
-use std::collections::HashMap;
+use krct_async::transaction::{InputTransaction, RejectionReason, Transaction};
+use std::collections::BTreeMap;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
 
-pub async fn run_tx(data: String) -> HashMap<AccountID, Account> {
+#[allow(dead_code)] // not every test uses it
+pub async fn run_tx(data: String) -> BTreeMap<AccountID, Account> {
+    run_tx_with(data, |service| service).await
+}
+
+/// Same as `run_tx`, but lets the test configure the service before it starts
+#[allow(dead_code)] // not every test uses it
+pub async fn run_tx_with<F>(data: String, configure: F) -> BTreeMap<AccountID, Account>
+where
+    F: FnOnce(Service) -> Service + Send + 'static,
+{
+    let accounts = run_service(data, configure).await.get_accounts().await;
+    for account in accounts.values() {
+        if let Some(violation) = account.invariant_violation() {
+            panic!("{}", violation);
+        }
+    }
+    accounts
+}
+
+/// Run transactions through the configured service and return the service after it stopped
+#[allow(dead_code)] // not every test uses it
+pub async fn run_service<F>(data: String, configure: F) -> Service
+where
+    F: FnOnce(Service) -> Service + Send + 'static,
+{
     let (tx_sender, rx) = tokio::sync::mpsc::channel(CHANNEL_BUUFER_SIZE);
 
     let data_handle: tokio::task::JoinHandle<anyhow::Result<()>> = tokio::spawn(async move {
-        //let mut rdr = csv::Reader::from_reader(data.as_bytes());
         let mut rdr = csv::ReaderBuilder::new()
             .flexible(true)
             .from_reader(data.as_bytes());
-        for result in rdr.deserialize::<InputTransaction>() {
-            if let Ok(record) = result {
-                if let Ok(tx) = Transaction::try_from(record) {
-                    tx_sender.send(Message::Tx(tx)).await?;
-                }
+        let headers = rdr.headers()?.clone();
+        for (seq, record) in rdr.records().flatten().enumerate() {
+            let line = record.position().map_or(0, |pos| pos.line());
+            if let Ok(tx) = record
+                .deserialize::<InputTransaction>(Some(&headers))
+                .map_err(anyhow::Error::from)
+                .and_then(Transaction::try_from)
+            {
+                let tx = tx.with_line(line).with_seq(seq as u64 + 1);
+                tx_sender.send(Message::Tx(tx)).await?;
             }
         }
         tx_sender.send(Message::Stop).await?;
@@ -24,12 +55,66 @@ pub async fn run_tx(data: String) -> HashMap<AccountID, Account> {
     });
 
     let service_handle = tokio::spawn(async move {
-        let mut service = Service::new(rx);
-        service.run().await;
-        service.get_accounts().await
+        let mut service = configure(Service::new(rx));
+        service.run().await.unwrap();
+        service
     });
 
-    let (_, accounts) = tokio::join!(data_handle, service_handle);
+    let (_, service) = tokio::join!(data_handle, service_handle);
+
+    service.unwrap()
+}
+
+/// Ids of failed transactions of the account with reasons of failure, in order of failure
+#[allow(dead_code)] // not every test uses it
+pub fn reasons(account: &Account) -> Vec<(TxID, RejectionReason)> {
+    account
+        .failed()
+        .unwrap()
+        .iter()
+        .map(|(tx, reason)| (tx.id(), *reason))
+        .collect()
+}
+
+/// File or directory in the temp dir unique to the test process, it is removed on drop
+#[allow(dead_code)] // not every test uses it
+pub struct TempPath(PathBuf);
+
+#[allow(dead_code)] // not every test uses it
+impl TempPath {
+    pub fn new(name: &str) -> Self {
+        Self(std::env::temp_dir().join(format!("krct_async_{}_{}", std::process::id(), name)))
+    }
+
+    /// Temp file filled with `data`
+    pub fn with_data(name: &str, data: impl AsRef<[u8]>) -> Self {
+        let path = Self::new(name);
+        std::fs::write(&path, data).unwrap();
+        path
+    }
+}
+
+impl Deref for TempPath {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for TempPath {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
 
-    return accounts.unwrap();
+impl Drop for TempPath {
+    fn drop(&mut self) {
+        // nothing to remove if the test didn't create it
+        let _ = if self.0.is_dir() {
+            std::fs::remove_dir_all(&self.0)
+        } else {
+            std::fs::remove_file(&self.0)
+        };
+    }
 }
@@ -0,0 +1,111 @@
+use krct_async::account::Account;
+use krct_async::primitives::*;
+use krct_async::transaction::{RejectionReason, TransactionType};
+
+mod common;
+
+#[tokio::test]
+async fn unlock_after_chargeback() {
+    let data = "\
+        type,client,tx,amount
+        deposit,1,1,1.1
+        deposit,1,2,2.0
+        dispute,1,1
+        chargeback,1,1
+        unlock,1,100
+        withdrawal,1,3,0.5
+        ";
+
+    let accounts = common::run_tx(data.to_owned()).await;
+    let account = accounts.get(&1).unwrap();
+
+    let verify_account = Account::new(1)
+        .set_available(Coin::new(15, 1))
+        .set_total(Coin::new(15, 1));
+
+    assert!(verify_account.check_amounts(account));
+    assert!(account.failed().unwrap().is_empty());
+
+    let audit = account
+        .audit_trail()
+        .iter()
+        .map(|tx| (tx.id(), tx.tx_type()))
+        .collect::<Vec<_>>();
+    assert_eq!(audit, vec![(100, TransactionType::Unlock)]);
+}
+
+#[tokio::test]
+async fn freeze_and_unlock() {
+    let data = "\
+        type,client,tx,amount
+        deposit,1,1,1.0
+        freeze,1,100
+        deposit,1,2,1.0
+        freeze,1,101
+        unlock,1,102
+        unlock,1,103
+        deposit,1,3,1.0
+        ";
+
+    let accounts = common::run_tx(data.to_owned()).await;
+    let account = accounts.get(&1).unwrap();
+
+    let verify_account = Account::new(1)
+        .set_available(Coin::new(2, 0))
+        .set_total(Coin::new(2, 0));
+
+    assert!(verify_account.check_amounts(account));
+    assert_eq!(
+        common::reasons(account),
+        vec![
+            (2, RejectionReason::AccountLocked),
+            (101, RejectionReason::AccountLocked),
+            (103, RejectionReason::NotLocked)
+        ]
+    );
+    assert_eq!(account.audit_trail().len(), 2);
+}
+
+#[tokio::test]
+async fn closed_account_is_final() {
+    let data = "\
+        type,client,tx,amount
+        deposit,1,1,1.0
+        close,1,100
+        unlock,1,101
+        deposit,1,2,1.0
+        ";
+
+    let accounts = common::run_tx(data.to_owned()).await;
+    let account = accounts.get(&1).unwrap();
+
+    let verify_account = Account::new(1)
+        .set_available(Coin::new(1, 0))
+        .set_total(Coin::new(1, 0))
+        .set_locked(true);
+
+    assert!(verify_account.check_amounts(account));
+    assert_eq!(
+        common::reasons(account),
+        vec![
+            (101, RejectionReason::AccountClosed),
+            (2, RejectionReason::AccountClosed)
+        ]
+    );
+}
+
+#[tokio::test]
+async fn admin_tx_id_is_not_global() {
+    let data = "\
+        type,client,tx,amount
+        deposit,1,1,1.0
+        freeze,2,1
+        ";
+
+    let accounts = common::run_tx(data.to_owned()).await;
+
+    assert!(accounts.get(&2).unwrap().failed().unwrap().is_empty());
+    assert!(Account::new(2)
+        .set_locked(true)
+        .check_amounts(accounts.get(&2).unwrap()));
+}
@@ -0,0 +1,150 @@
+use common::TempPath;
+use krct_async::account::{DisputePolicy, DisputeWindow};
+use krct_async::budget::MemoryBudget;
+use krct_async::generator::{Generator, GeneratorConfig};
+use krct_async::primitives::*;
+use krct_async::store::StoreBackend;
+use std::sync::atomic::Ordering;
+
+mod common;
+
+fn generated() -> String {
+    let config = GeneratorConfig {
+        clients: 10,
+        transactions: 5000,
+        dispute_rate: 0.3,
+        resolution_rate: 0.3,
+        chargeback_rate: 0.1,
+        duplicate_rate: 0.02,
+        seed: 3,
+        ..Default::default()
+    };
+    let mut data = Vec::new();
+    Generator::new(config)
+        .unwrap()
+        .write_csv(&mut data)
+        .unwrap();
+    String::from_utf8(data).unwrap()
+}
+
+fn sorted_lines(path: &std::path::Path) -> Vec<String> {
+    let report = std::fs::read_to_string(path).unwrap();
+    let mut lines = report.lines().map(str::to_owned).collect::<Vec<_>>();
+    lines.sort();
+    lines
+}
+
+#[tokio::test]
+async fn budgeted_run_matches_unbounded_run() {
+    let policy = DisputePolicy {
+        window: Some(DisputeWindow::Transactions(100)),
+        max_disputes: Some(2),
+    };
+    let unbounded = common::run_tx_with(generated(), move |service| {
+        service.with_dispute_policy(policy)
+    })
+    .await;
+
+    let streamed = TempPath::new("streamed.csv");
+    let budget = MemoryBudget::new(20).with_rejected(RejectedStream::create(&streamed).unwrap());
+    let service_budget = budget.clone();
+    let bounded = common::run_tx_with(generated(), move |service| {
+        service
+            .with_dispute_policy(policy)
+            .with_memory_budget(service_budget)
+    })
+    .await;
+
+    for (id, account) in &unbounded {
+        assert!(account.check_amounts(&bounded[id]), "client {}", id);
+        assert!(bounded[id].failed().unwrap().is_empty());
+        assert!(bounded[id].statement().is_empty());
+        assert!(bounded[id].events().len() <= 20);
+        // folded events still rebuild the latest balances
+        let last = account.events().last().unwrap().0;
+        assert_eq!(
+            bounded[id].balance_at(last),
+            account.balance_at(last),
+            "client {}",
+            id
+        );
+    }
+
+    // the same rejections are reported, but interleaved in order of rejection
+    budget.rejected.as_ref().unwrap().flush().unwrap();
+    let expected_path = TempPath::new("expected.csv");
+    let accounts = unbounded.into_values().collect::<Vec<_>>();
+    write_rejected(&expected_path, &accounts).unwrap();
+    let expected = sorted_lines(&expected_path);
+    assert!(expected.len() > 100);
+    assert_eq!(sorted_lines(&streamed), expected);
+
+    let metrics = &budget.metrics;
+    assert!(metrics.compactions.load(Ordering::Relaxed) > 0);
+    assert!(metrics.compacted.load(Ordering::Relaxed) > 0);
+    assert!(metrics.expired.load(Ordering::Relaxed) > 0);
+    assert!(metrics.events_folded.load(Ordering::Relaxed) > 0);
+    assert_eq!(
+        metrics.failed_evicted.load(Ordering::Relaxed) as usize,
+        expected.len() - 1 // without header
+    );
+}
+
+#[tokio::test]
+async fn budgeted_run_compacts_sled_store_in_place() {
+    let policy = DisputePolicy {
+        window: Some(DisputeWindow::Transactions(100)),
+        max_disputes: None,
+    };
+    let unbounded = common::run_tx_with(generated(), move |service| {
+        service.with_dispute_policy(policy)
+    })
+    .await;
+
+    let path = TempPath::new("budget.sled");
+    let backend = StoreBackend::sled(&path).unwrap();
+    let budget = MemoryBudget::new(20);
+    let service_budget = budget.clone();
+    let bounded = common::run_tx_with(generated(), move |service| {
+        service
+            .with_dispute_policy(policy)
+            .with_store(backend)
+            .with_memory_budget(service_budget)
+    })
+    .await;
+
+    for (id, account) in &unbounded {
+        assert!(account.check_amounts(&bounded[id]), "client {}", id);
+        assert!(bounded[id].failed().unwrap().is_empty());
+    }
+    let metrics = &budget.metrics;
+    assert!(metrics.compacted.load(Ordering::Relaxed) > 0);
+    assert!(metrics.expired.load(Ordering::Relaxed) > 0);
+}
+
+#[tokio::test]
+async fn compact_owners_check_ids_of_other_clients() {
+    let data = "\
+        type,client,tx,amount
+        deposit,5,1,10.0
+        deposit,65535,2,3.0
+        deposit,6,1,1.0
+        dispute,4,2
+        dispute,5,1
+        withdrawal,65535,1,1.0
+        deposit,5,1,1.0
+        ";
+    let budget = MemoryBudget::new(20);
+    let service_budget = budget.clone();
+    let accounts = common::run_tx_with(data.to_owned(), move |service| {
+        service.with_memory_budget(service_budget)
+    })
+    .await;
+
+    assert_eq!(accounts[&5].held(), Coin::new(10, 0));
+    assert_eq!(accounts[&65535].total(), Coin::new(3, 0));
+    assert_eq!(accounts[&6].total(), Coin::new(0, 0));
+    assert_eq!(accounts[&4].total(), Coin::new(0, 0));
+    // duplicates of ids owned by other clients, the reference of client 4 and the duplicate of client 5
+    assert_eq!(budget.metrics.failed_evicted.load(Ordering::Relaxed), 4);
+}
@@ -1,5 +1,6 @@
 use krct_async::account::Account;
 use krct_async::primitives::*;
+use krct_async::transaction::RejectionReason;
 
 mod common;
 
@@ -187,6 +188,7 @@ async fn check_chargeback_lock() {
 async fn single_withdrawl_chargeback() {
     let data = "\
         type,client,tx,amount
+        deposit,1,2,2.0
         withdrawal,1,1,1.1
         dispute,1,1
         chargeback,1,1
@@ -194,7 +196,28 @@ async fn single_withdrawl_chargeback() {
 
     let accounts = common::run_tx(data.to_owned()).await;
 
-    let verify_account = Account::new(1).set_locked(true);
+    let verify_account = Account::new(1)
+        .set_available(Coin::new(2, 0))
+        .set_total(Coin::new(2, 0))
+        .set_locked(true);
 
     assert!(verify_account.check_amounts(accounts.get(&1).unwrap()));
 }
+
+#[tokio::test]
+async fn locked_account_rejects_transactions() {
+    let data = "\
+        type,client,tx,amount
+        deposit,1,1,1.1
+        dispute,1,1
+        chargeback,1,1
+        deposit,1,2,5.0
+        ";
+
+    let accounts = common::run_tx(data.to_owned()).await;
+
+    let failed = accounts.get(&1).unwrap().failed().unwrap();
+    assert_eq!(failed.len(), 1);
+    assert_eq!(failed[0].0.id(), 2);
+    assert_eq!(failed[0].1, RejectionReason::AccountLocked);
+}
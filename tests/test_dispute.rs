@@ -1,5 +1,6 @@
 use krct_async::account::Account;
 use krct_async::primitives::*;
+use krct_async::transaction::RejectionReason;
 
 mod common;
 
@@ -127,6 +128,7 @@ async fn multi_acc_dispute() {
 async fn single_withdrawl_dispute() {
     let data = "\
         type,client,tx,amount
+        deposit,1,2,2.0
         withdrawal,1,1,1.1
         dispute,1,1
         ";
@@ -134,9 +136,30 @@ async fn single_withdrawl_dispute() {
     let accounts = common::run_tx(data.to_owned()).await;
 
     let verify_account = Account::new(1)
-        .set_available(Coin::new(0, 0))
-        .set_total(-Coin::new(11, 1))
+        .set_available(Coin::new(2, 0))
+        .set_total(Coin::new(9, 1))
         .set_held(-Coin::new(11, 1));
 
     assert!(verify_account.check_amounts(accounts.get(&1).unwrap()));
 }
+
+#[tokio::test]
+async fn rejected_dispute_reasons() {
+    let data = "\
+        type,client,tx,amount
+        deposit,1,1,1.1
+        dispute,1,2
+        dispute,1,1
+        dispute,1,1
+        ";
+
+    let accounts = common::run_tx(data.to_owned()).await;
+
+    assert_eq!(
+        common::reasons(&accounts[&1]),
+        vec![
+            (2, RejectionReason::MissingParent),
+            (1, RejectionReason::AlreadyDisputed)
+        ]
+    );
+}
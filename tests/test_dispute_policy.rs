@@ -0,0 +1,108 @@
+use krct_async::account::{Account, DisputePolicy, DisputeWindow};
+use krct_async::primitives::*;
+use krct_async::transaction::RejectionReason;
+
+mod common;
+
+#[tokio::test]
+async fn dispute_window_by_timestamp() {
+    let data = "\
+        type,client,tx,amount,timestamp
+        deposit,1,1,1.0,1000
+        deposit,1,2,2.0,1000
+        deposit,1,3,4.0,
+        dispute,1,1,,1100
+        dispute,1,2,,1101
+        dispute,1,3,,5000
+        ";
+
+    let policy = DisputePolicy {
+        window: Some(DisputeWindow::Seconds(100)),
+        max_disputes: None,
+    };
+    let accounts = common::run_tx_with(data.to_owned(), move |service| {
+        service.with_dispute_policy(policy)
+    })
+    .await;
+    let account = accounts.get(&1).unwrap();
+
+    // dispute of tx 3 can't be checked, as deposit has no timestamp
+    let verify_account = Account::new(1)
+        .set_available(Coin::new(6, 0))
+        .set_held(Coin::new(1, 0))
+        .set_total(Coin::new(7, 0));
+
+    assert!(verify_account.check_amounts(account));
+    assert_eq!(
+        common::reasons(account),
+        vec![
+            (2, RejectionReason::DisputeWindowExpired),
+            (3, RejectionReason::MissingTimestamp)
+        ]
+    );
+}
+
+#[tokio::test]
+async fn dispute_window_by_sequence() {
+    let data = "\
+        type,client,tx,amount
+        deposit,1,1,1.0
+        deposit,1,2,2.0
+        dispute,1,2
+        dispute,1,1
+        ";
+
+    let policy = DisputePolicy {
+        window: Some(DisputeWindow::Transactions(2)),
+        max_disputes: None,
+    };
+    let accounts = common::run_tx_with(data.to_owned(), move |service| {
+        service.with_dispute_policy(policy)
+    })
+    .await;
+    let account = accounts.get(&1).unwrap();
+
+    let verify_account = Account::new(1)
+        .set_available(Coin::new(1, 0))
+        .set_held(Coin::new(2, 0))
+        .set_total(Coin::new(3, 0));
+
+    assert!(verify_account.check_amounts(account));
+    assert_eq!(
+        common::reasons(account),
+        vec![(1, RejectionReason::DisputeWindowExpired)]
+    );
+}
+
+#[tokio::test]
+async fn max_disputes() {
+    let data = "\
+        type,client,tx,amount
+        deposit,1,1,1.0
+        dispute,1,1
+        resolve,1,1
+        dispute,1,1
+        resolve,1,1
+        dispute,1,1
+        ";
+
+    let policy = DisputePolicy {
+        window: None,
+        max_disputes: Some(2),
+    };
+    let accounts = common::run_tx_with(data.to_owned(), move |service| {
+        service.with_dispute_policy(policy)
+    })
+    .await;
+    let account = accounts.get(&1).unwrap();
+
+    let verify_account = Account::new(1)
+        .set_available(Coin::new(1, 0))
+        .set_total(Coin::new(1, 0));
+
+    assert!(verify_account.check_amounts(account));
+    assert_eq!(
+        common::reasons(account),
+        vec![(1, RejectionReason::TooManyDisputes)]
+    );
+}
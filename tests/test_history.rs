@@ -0,0 +1,112 @@
+use krct_async::account::{AccountEvent, Balance};
+use krct_async::primitives::*;
+
+mod common;
+
+const DATA: &str = "\
+    type,client,tx,amount
+    deposit,1,1,10.0
+    deposit,2,2,5.0
+    withdrawal,1,3,3.0
+    dispute,1,1,4.0
+    withdrawal,1,4,20.0
+    resolve,1,1,1.0
+    chargeback,1,1
+    unlock,1,0
+    dispute,1,3
+    ";
+
+#[tokio::test]
+async fn events_of_account() {
+    let accounts = common::run_tx(DATA.to_owned()).await;
+    let coin = |num, scale| Coin::new(num, scale);
+
+    // rejected withdrawal has no events, disputed withdrawal is held with negative amount
+    assert_eq!(
+        accounts[&1].events(),
+        [
+            (1, AccountEvent::Credited(coin(10, 0))),
+            (3, AccountEvent::Credited(-coin(3, 0))),
+            (4, AccountEvent::Held(coin(4, 0))),
+            (6, AccountEvent::Released(coin(1, 0))),
+            (7, AccountEvent::ChargedBack(coin(3, 0))),
+            (7, AccountEvent::Locked),
+            (8, AccountEvent::Unlocked),
+            (9, AccountEvent::Held(-coin(3, 0))),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn balance_at_matches_statement() {
+    let accounts = common::run_tx_with(DATA.to_owned(), |service| {
+        service.with_statement_clients([1, 2])
+    })
+    .await;
+
+    for account in accounts.values() {
+        assert_eq!(account.balance_at(0), Some(Balance::default()));
+        for entry in account.statement() {
+            let balance = Balance {
+                available: entry.available,
+                held: entry.held,
+                total: entry.total,
+                locked: entry.locked,
+            };
+            assert_eq!(account.balance_at(entry.tx.seq()), Some(balance));
+        }
+        let last = account.balance_at(u64::MAX).unwrap();
+        assert_eq!(
+            (last.available, last.held, last.total, last.locked),
+            (
+                account.available(),
+                account.held(),
+                account.total(),
+                account.is_locked()
+            )
+        );
+    }
+
+    // balance between transactions of the account is the one after the previous transaction
+    assert_eq!(accounts[&1].balance_at(5), accounts[&1].balance_at(4));
+}
+
+#[tokio::test]
+async fn service_balance_at() {
+    let service = common::run_service(DATA.to_owned(), |service| service).await;
+
+    let balance = service.balance_at(1, 4).await.unwrap();
+    assert_eq!(balance.available, Coin::new(3, 0));
+    assert_eq!(balance.held, Coin::new(4, 0));
+    assert_eq!(balance.total, Coin::new(7, 0));
+    assert!(!balance.locked);
+    assert!(service.balance_at(3, 4).await.is_none());
+}
+
+#[tokio::test]
+async fn compacted_events_keep_later_balances() {
+    let mut service = common::run_service(DATA.to_owned(), |service| service).await;
+    let before = service.get_accounts().await;
+
+    service.compact_events(4).await;
+    let accounts = service.get_accounts().await;
+    let account = &accounts[&1];
+    assert_eq!(account.events().len(), 5);
+    assert_eq!(account.events()[0].0, 6);
+    assert!(account.balance_at(3).is_none());
+    for seq in 4..=9 {
+        assert_eq!(
+            account.balance_at(seq),
+            before[&1].balance_at(seq),
+            "seq {}",
+            seq
+        );
+    }
+
+    // checkpoint is kept by the snapshot
+    let snapshot = service.snapshot().await.unwrap();
+    let (_, receiver) = tokio::sync::mpsc::channel(CHANNEL_BUUFER_SIZE);
+    let restored = krct_async::service::Service::new(receiver).with_snapshot(snapshot);
+    assert!(restored.balance_at(1, 3).await.is_none());
+    assert_eq!(restored.balance_at(1, 4).await, before[&1].balance_at(4));
+}
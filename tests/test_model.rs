@@ -0,0 +1,169 @@
+//! Random transactions run through `Service` and compared with a synchronous reference model of the account rules
+use krct_async::primitives::*;
+use proptest::prelude::*;
+use std::collections::{BTreeMap, HashMap};
+
+mod common;
+
+#[derive(Debug, Clone, Copy)]
+enum Op {
+    Deposit(AccountID, TxID, Coin),
+    Withdrawal(AccountID, TxID, Coin),
+    Dispute(AccountID, TxID),
+    Resolve(AccountID, TxID),
+    Chargeback(AccountID, TxID),
+}
+
+impl Op {
+    fn to_csv(self) -> String {
+        match self {
+            Op::Deposit(client, tx, amount) => format!("deposit,{},{},{}", client, tx, amount),
+            Op::Withdrawal(client, tx, amount) => {
+                format!("withdrawal,{},{},{}", client, tx, amount)
+            }
+            Op::Dispute(client, tx) => format!("dispute,{},{},", client, tx),
+            Op::Resolve(client, tx) => format!("resolve,{},{},", client, tx),
+            Op::Chargeback(client, tx) => format!("chargeback,{},{},", client, tx),
+        }
+    }
+}
+
+/// Few clients and ids, so transactions often refer to each other
+fn op() -> impl Strategy<Value = Op> {
+    let client = 1..=4u16;
+    let tx = 1..=12u32;
+    let amount = (1..=10_000i64).prop_map(|cents| Coin::new(cents, 2));
+    prop_oneof![
+        3 => (client.clone(), tx.clone(), amount.clone()).prop_map(|(c, t, a)| Op::Deposit(c, t, a)),
+        2 => (client.clone(), tx.clone(), amount).prop_map(|(c, t, a)| Op::Withdrawal(c, t, a)),
+        2 => (client.clone(), tx.clone()).prop_map(|(c, t)| Op::Dispute(c, t)),
+        1 => (client.clone(), tx.clone()).prop_map(|(c, t)| Op::Resolve(c, t)),
+        1 => (client, tx).prop_map(|(c, t)| Op::Chargeback(c, t)),
+    ]
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+struct ModelAccount {
+    available: Coin,
+    held: Coin,
+    total: Coin,
+    locked: bool,
+}
+
+/// Applied deposit or withdrawal, `amount` is negative for withdrawal
+struct ModelTx {
+    amount: Coin,
+    disputed: bool,
+    charged_back: bool,
+}
+
+/// Reference implementation: transactions are applied one by one in input order
+#[derive(Default)]
+struct Model {
+    accounts: BTreeMap<AccountID, ModelAccount>,
+    owners: HashMap<TxID, AccountID>, // id of every applied deposit and withdrawal
+    txs: HashMap<TxID, ModelTx>,
+}
+
+impl Model {
+    fn apply(&mut self, op: Op) {
+        let (client, id) = match op {
+            Op::Deposit(c, t, _)
+            | Op::Withdrawal(c, t, _)
+            | Op::Dispute(c, t)
+            | Op::Resolve(c, t)
+            | Op::Chargeback(c, t) => (c, t),
+        };
+        let acc = self.accounts.entry(client).or_default();
+        let owner = match (self.owners.get(&id), op) {
+            (Some(&owner), _) => owner,
+            (None, Op::Deposit(..) | Op::Withdrawal(..)) => client, // claimed if applied
+            (None, _) => return,                                    // reference to unknown id
+        };
+        if owner != client || acc.locked {
+            return;
+        }
+
+        match op {
+            Op::Deposit(_, _, amount) | Op::Withdrawal(_, _, amount) => {
+                if self.txs.contains_key(&id) {
+                    return; // duplicate
+                }
+                let amount = match op {
+                    Op::Withdrawal(..) if acc.available < amount => return, // insufficient funds
+                    Op::Withdrawal(..) => -amount,
+                    _ => amount,
+                };
+                acc.available += amount;
+                acc.total += amount;
+                self.owners.insert(id, client);
+                self.txs.insert(
+                    id,
+                    ModelTx {
+                        amount,
+                        disputed: false,
+                        charged_back: false,
+                    },
+                );
+            }
+            Op::Dispute(..) => match self.txs.get_mut(&id) {
+                Some(tx) if !tx.disputed && !tx.charged_back => {
+                    tx.disputed = true;
+                    acc.available -= tx.amount;
+                    acc.held += tx.amount;
+                }
+                _ => {}
+            },
+            Op::Resolve(..) => match self.txs.get_mut(&id) {
+                Some(tx) if tx.disputed => {
+                    tx.disputed = false;
+                    acc.available += tx.amount;
+                    acc.held -= tx.amount;
+                }
+                _ => {}
+            },
+            Op::Chargeback(..) => match self.txs.get_mut(&id) {
+                Some(tx) if tx.disputed => {
+                    tx.disputed = false;
+                    tx.charged_back = true;
+                    acc.held -= tx.amount;
+                    acc.total -= tx.amount;
+                    acc.locked = true;
+                }
+                _ => {}
+            },
+        }
+    }
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(128))]
+
+    #[test]
+    fn service_matches_model(ops in prop::collection::vec(op(), 1..150)) {
+        let mut model = Model::default();
+        let mut data = String::from("type,client,tx,amount\n");
+        for &op in &ops {
+            model.apply(op);
+            data.push_str(&op.to_csv());
+            data.push('\n');
+        }
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let accounts = runtime.block_on(common::run_tx(data));
+
+        let actual = accounts
+            .iter()
+            .map(|(&id, acc)| {
+                let state = ModelAccount {
+                    available: acc.available(),
+                    held: acc.held(),
+                    total: acc.total(),
+                    locked: acc.is_locked(),
+                };
+                (id, state)
+            })
+            .collect::<BTreeMap<_, _>>();
+        prop_assert_eq!(actual, model.accounts);
+    }
+}
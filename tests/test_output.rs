@@ -0,0 +1,94 @@
+use krct_async::primitives::*;
+use std::str::FromStr;
+
+mod common;
+
+async fn results(data: &str, format: OutputFormat) -> String {
+    let accounts = common::run_tx(data.to_owned()).await;
+    let accounts = accounts.into_values().collect::<Vec<_>>();
+
+    let mut out = Vec::new();
+    write_results_to(&mut out, accounts, format).unwrap();
+    String::from_utf8(out).unwrap()
+}
+
+const DATA: &str = "\
+    type,client,tx,amount
+    deposit,1,1,1.5
+    deposit,2,2,2.0001
+    dispute,2,2
+    ";
+
+#[tokio::test]
+async fn output_csv() {
+    assert_eq!(
+        results(DATA, OutputFormat::Csv).await,
+        "\
+client,available,held,total,locked
+1,1.5,0.0000,1.5,false
+2,0.0000,2.0001,2.0001,false
+"
+    );
+}
+
+#[tokio::test]
+async fn output_json() {
+    assert_eq!(
+        results(DATA, OutputFormat::Json).await,
+        r#"[{"client":1,"available":"1.5","held":"0.0000","total":"1.5","locked":false},{"client":2,"available":"0.0000","held":"2.0001","total":"2.0001","locked":false}]
+"#
+    );
+}
+
+#[tokio::test]
+async fn output_jsonl() {
+    assert_eq!(
+        results(DATA, OutputFormat::Jsonl).await,
+        r#"{"client":1,"available":"1.5","held":"0.0000","total":"1.5","locked":false}
+{"client":2,"available":"0.0000","held":"2.0001","total":"2.0001","locked":false}
+"#
+    );
+}
+
+#[tokio::test]
+async fn accounts_sorted() {
+    let data = "\
+        type,client,tx,amount
+        deposit,3,1,1.0
+        deposit,1,2,2.0
+        deposit,2,3,1.0
+        deposit,4,4,3.0
+        dispute,4,4
+        ";
+    let accounts = common::run_tx(data.to_owned()).await;
+    // service returns accounts ordered by client id
+    assert_eq!(
+        accounts.keys().copied().collect::<Vec<_>>(),
+        vec![1, 2, 3, 4]
+    );
+
+    let mut accounts = accounts.into_values().collect::<Vec<_>>();
+    let ids = |accounts: &[krct_async::account::Account]| {
+        accounts.iter().map(|acc| acc.id()).collect::<Vec<_>>()
+    };
+
+    sort_accounts(&mut accounts, SortBy::Total);
+    assert_eq!(ids(&accounts), vec![4, 1, 2, 3]);
+    sort_accounts(&mut accounts, SortBy::Available);
+    assert_eq!(ids(&accounts), vec![1, 2, 3, 4]);
+    sort_accounts(&mut accounts, SortBy::Held);
+    assert_eq!(ids(&accounts), vec![4, 1, 2, 3]);
+    sort_accounts(&mut accounts, SortBy::Client);
+    assert_eq!(ids(&accounts), vec![1, 2, 3, 4]);
+}
+
+#[test]
+fn output_format_from_str() {
+    assert_eq!(OutputFormat::from_str("JSON").unwrap(), OutputFormat::Json);
+    assert_eq!(
+        OutputFormat::from_str("jsonl").unwrap(),
+        OutputFormat::Jsonl
+    );
+    assert_eq!(OutputFormat::from_str("csv").unwrap(), OutputFormat::Csv);
+    assert!(OutputFormat::from_str("xml").is_err());
+}
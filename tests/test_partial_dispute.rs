@@ -0,0 +1,128 @@
+use krct_async::account::Account;
+use krct_async::primitives::*;
+use krct_async::transaction::RejectionReason;
+
+mod common;
+
+#[tokio::test]
+async fn partial_dispute() {
+    let data = "\
+        type,client,tx,amount
+        deposit,1,1,10.0
+        dispute,1,1,4.0
+        ";
+
+    let accounts = common::run_tx(data.to_owned()).await;
+
+    let verify_account = Account::new(1)
+        .set_available(Coin::new(6, 0))
+        .set_held(Coin::new(4, 0))
+        .set_total(Coin::new(10, 0));
+
+    assert!(verify_account.check_amounts(accounts.get(&1).unwrap()));
+}
+
+#[tokio::test]
+async fn several_partial_disputes() {
+    let data = "\
+        type,client,tx,amount
+        deposit,1,1,10.0
+        dispute,1,1,4.0
+        dispute,1,1,5.0
+        dispute,1,1,1.5
+        dispute,1,1
+        dispute,1,1
+        ";
+
+    let accounts = common::run_tx(data.to_owned()).await;
+    let account = accounts.get(&1).unwrap();
+
+    // dispute without amount holds the rest of transaction
+    let verify_account = Account::new(1)
+        .set_held(Coin::new(10, 0))
+        .set_total(Coin::new(10, 0));
+
+    assert!(verify_account.check_amounts(account));
+    assert_eq!(
+        common::reasons(account),
+        vec![
+            (1, RejectionReason::DisputeExceedsUndisputed),
+            (1, RejectionReason::AlreadyDisputed)
+        ]
+    );
+}
+
+#[tokio::test]
+async fn partial_resolve_and_chargeback() {
+    let data = "\
+        type,client,tx,amount
+        deposit,1,1,10.0
+        dispute,1,1,6.0
+        resolve,1,1,2.0
+        resolve,1,1,5.0
+        chargeback,1,1,3.0
+        ";
+
+    let accounts = common::run_tx(data.to_owned()).await;
+    let account = accounts.get(&1).unwrap();
+
+    let verify_account = Account::new(1)
+        .set_available(Coin::new(6, 0))
+        .set_held(Coin::new(1, 0))
+        .set_total(Coin::new(7, 0))
+        .set_locked(true);
+
+    assert!(verify_account.check_amounts(account));
+    assert_eq!(
+        common::reasons(account),
+        vec![(1, RejectionReason::ExceedsDisputed)]
+    );
+}
+
+#[tokio::test]
+async fn redispute_after_partial_chargeback() {
+    let data = "\
+        type,client,tx,amount
+        deposit,1,1,10.0
+        dispute,1,1,4.0
+        chargeback,1,1
+        unlock,1,100
+        dispute,1,1,6.5
+        dispute,1,1
+        ";
+
+    let accounts = common::run_tx(data.to_owned()).await;
+    let account = accounts.get(&1).unwrap();
+
+    // charged back part can't be disputed again
+    let verify_account = Account::new(1)
+        .set_held(Coin::new(6, 0))
+        .set_total(Coin::new(6, 0));
+
+    assert!(verify_account.check_amounts(account));
+    assert_eq!(
+        common::reasons(account),
+        vec![(1, RejectionReason::DisputeExceedsUndisputed)]
+    );
+}
+
+#[tokio::test]
+async fn partial_withdrawal_dispute() {
+    let data = "\
+        type,client,tx,amount
+        deposit,1,1,10.0
+        withdrawal,1,2,4.0
+        dispute,1,2,1.0
+        resolve,1,2
+        dispute,1,2,3.0
+        ";
+
+    let accounts = common::run_tx(data.to_owned()).await;
+
+    let verify_account = Account::new(1)
+        .set_available(Coin::new(9, 0))
+        .set_held(-Coin::new(3, 0))
+        .set_total(Coin::new(6, 0));
+
+    assert!(verify_account.check_amounts(accounts.get(&1).unwrap()));
+}
@@ -0,0 +1,466 @@
+use common::TempPath;
+use krct_async::primitives::*;
+use krct_async::transaction::TransactionType;
+use tokio::sync::mpsc;
+
+mod common;
+
+/// Run reader over `data` and collect everything it sent and reported
+async fn read(name: &str, data: &[u8]) -> (ReadSummary, Vec<Message>, String) {
+    let (summary, messages, errors) = read_with(name, data, ReaderConfig::default()).await;
+    (summary.unwrap(), messages, errors)
+}
+
+async fn read_with(
+    name: &str,
+    data: &[u8],
+    config: ReaderConfig,
+) -> (anyhow::Result<ReadSummary>, Vec<Message>, String) {
+    let path = TempPath::with_data(name, data);
+    let input = path.to_path_buf().into();
+    let (sender, mut receiver) = mpsc::channel(CHANNEL_BUUFER_SIZE);
+    let reader = tokio::spawn(async move {
+        let mut errors = Vec::new();
+        let summary = run_reader(input, sender, &mut errors, &config).await;
+        (summary, String::from_utf8(errors).unwrap())
+    });
+
+    let mut messages = Vec::new();
+    while let Some(message) = receiver.recv().await {
+        messages.push(message);
+    }
+    let (summary, errors) = reader.await.unwrap();
+    (summary, messages, errors)
+}
+
+#[tokio::test]
+async fn reader_continues_after_bad_rows() {
+    let data = "\
+type,client,tx,amount
+deposit,1,1,1.0
+deposit,1,x,1.0
+transfer,1,2,1.0
+
+deposit,1,3,-1.0
+withdrawal,1,4,0.5
+";
+
+    let (summary, messages, errors) = read("bad_rows.csv", data.as_bytes()).await;
+
+    assert_eq!(
+        summary,
+        ReadSummary {
+            rows_read: 5,
+            rows_parsed: 2,
+            rows_rejected: 3,
+            rows_skipped: 0,
+        }
+    );
+
+    let txs = messages
+        .iter()
+        .filter_map(|message| match message {
+            Message::Tx(tx) => Some((tx.id(), tx.tx_type(), tx.line())),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        txs,
+        vec![
+            (1, TransactionType::Deposit, 2),
+            (4, TransactionType::Withdrawal, 7)
+        ]
+    );
+    assert!(matches!(messages.last(), Some(Message::Stop)));
+
+    let lines = errors.lines().collect::<Vec<_>>();
+    assert_eq!(lines.len(), 3);
+    assert!(lines[0].starts_with("line 3: ") && lines[0].ends_with(": deposit,1,x,1.0"));
+    assert!(lines[1].starts_with("line 4: Unknown TransactionType: transfer"));
+    assert!(lines[2].starts_with("line 6: Negative amount: deposit,1,3,-1.0"));
+}
+
+#[tokio::test]
+async fn reader_reports_invalid_utf8() {
+    let data = b"type,client,tx,amount\ndeposit,1,1\xff,1.0\ndeposit,1,2,1.0\n";
+    let (summary, _, errors) = read("utf8.csv", data).await;
+
+    assert_eq!(summary.rows_parsed, 1);
+    assert_eq!(summary.rows_rejected, 1);
+    assert!(errors.starts_with("line 2: "));
+    assert!(errors.trim_end().ends_with(": deposit,1,1\u{FFFD},1.0"));
+}
+
+#[tokio::test]
+async fn reader_reports_invalid_utf8_json_line() {
+    let data =
+        b"{\"type\": \"deposit\", \"client\": 1, \"tx\": \"1\xff\", \"amount\": \"1.0\"}\r\n\
+        {\"type\": \"deposit\", \"client\": 1, \"tx\": 2, \"amount\": \"1.0\"}\n";
+    let (summary, messages, errors) = read("utf8.jsonl", data).await;
+
+    assert_eq!(summary.rows_parsed, 1);
+    assert_eq!(summary.rows_rejected, 1);
+    assert_eq!(tx_ids(&messages), vec![(2, 2)]);
+    assert!(errors.starts_with("line 1: "), "{}", errors);
+    assert!(errors.ends_with(
+        ": {\"type\": \"deposit\", \"client\": 1, \"tx\": \"1\u{FFFD}\", \"amount\": \"1.0\"}\n"
+    ));
+}
+
+#[tokio::test]
+async fn reader_fails_if_service_stopped() {
+    let path = TempPath::with_data("stopped.csv", b"type,client,tx,amount\ndeposit,1,1,1.0\n");
+    let (sender, receiver) = mpsc::channel(CHANNEL_BUUFER_SIZE);
+    drop(receiver);
+
+    let res = run_reader(
+        path.to_path_buf().into(),
+        sender,
+        &mut std::io::sink(),
+        &ReaderConfig::default(),
+    )
+    .await;
+
+    assert_eq!(
+        res.unwrap_err().to_string(),
+        "service stopped before the end of input"
+    );
+}
+
+#[tokio::test]
+async fn strict_reader_aborts_on_bad_row() {
+    let data = "\
+type,client,tx,amount
+deposit,1,1,1.0
+deposit,1,x,1.0
+deposit,1,2,1.0
+";
+
+    let config = ReaderConfig {
+        strict: true,
+        ..Default::default()
+    };
+    let (summary, messages, errors) = read_with("strict.csv", data.as_bytes(), config).await;
+
+    let err = summary.unwrap_err().to_string();
+    assert!(err.starts_with("line 3: "), "{}", err);
+    assert!(err.ends_with(": deposit,1,x,1.0"), "{}", err);
+    assert!(errors.is_empty());
+
+    // only transaction before the bad row is sent and service is not stopped
+    assert_eq!(messages.len(), 1);
+    assert!(matches!(&messages[0], Message::Tx(tx) if tx.id() == 1));
+}
+
+#[tokio::test]
+async fn strict_reader_rejects_excess_precision() {
+    let data = "\
+type,client,tx,amount
+deposit,1,1,1.0
+withdrawal,1,2,0.12345
+";
+
+    let config = ReaderConfig {
+        strict: true,
+        ..Default::default()
+    };
+    let (summary, _, _) = read_with("strict_precision.csv", data.as_bytes(), config).await;
+
+    assert_eq!(
+        summary.unwrap_err().to_string(),
+        "line 3: Amount 0.12345 has more than 4 decimal places: withdrawal,1,2,0.12345"
+    );
+}
+
+/// Run reader over several inputs and collect everything it sent and reported
+async fn read_inputs(
+    inputs: Vec<Input>,
+    config: ReaderConfig,
+) -> (ReadSummary, Vec<Message>, String) {
+    let (sender, mut receiver) = mpsc::channel(CHANNEL_BUUFER_SIZE);
+    let reader = tokio::spawn(async move {
+        let mut errors = Vec::new();
+        let summary = run_readers(inputs, sender, &mut errors, &config).await;
+        (summary.unwrap(), String::from_utf8(errors).unwrap())
+    });
+
+    let mut messages = Vec::new();
+    while let Some(message) = receiver.recv().await {
+        messages.push(message);
+    }
+    let (summary, errors) = reader.await.unwrap();
+    (summary, messages, errors)
+}
+
+fn tx_ids(messages: &[Message]) -> Vec<(u32, u64)> {
+    messages
+        .iter()
+        .filter_map(|message| match message {
+            Message::Tx(tx) => Some((tx.id(), tx.seq())),
+            _ => None,
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn reader_reads_inputs_in_order() {
+    let first = TempPath::with_data(
+        "multi_1.csv",
+        b"type,client,tx,amount\ndeposit,1,1,1.0\ndeposit,1,x,1.0\n",
+    );
+    let second = TempPath::with_data("multi_2.csv", b"type,client,tx,amount\ndeposit,2,2,1.0\n");
+
+    let inputs = vec![
+        Input::File(first.to_path_buf()),
+        Input::File(second.to_path_buf()),
+    ];
+    let (summary, messages, errors) = read_inputs(inputs, ReaderConfig::default()).await;
+
+    assert_eq!(
+        summary,
+        ReadSummary {
+            rows_read: 3,
+            rows_parsed: 2,
+            rows_rejected: 1,
+            rows_skipped: 0,
+        }
+    );
+    // sequence continues in the second input
+    assert_eq!(tx_ids(&messages), vec![(1, 1), (2, 3)]);
+    assert!(matches!(messages.last(), Some(Message::Stop)));
+    assert!(
+        errors.starts_with(&format!("{}: line 3: ", first.display())),
+        "{}",
+        errors
+    );
+}
+
+#[tokio::test]
+async fn reader_reads_inputs_concurrently() {
+    let first = TempPath::with_data(
+        "concurrent_1.csv",
+        b"type,client,tx,amount\ndeposit,1,1,1.0\n",
+    );
+    let second = TempPath::with_data(
+        "concurrent_2.csv",
+        b"type,client,tx,amount\ndeposit,2,2,1.0\n",
+    );
+
+    let inputs = vec![
+        Input::File(first.to_path_buf()),
+        Input::File(second.to_path_buf()),
+    ];
+    let config = ReaderConfig {
+        concurrent: true,
+        ..Default::default()
+    };
+    let (summary, messages, _) = read_inputs(inputs, config).await;
+
+    assert_eq!(summary.rows_parsed, 2);
+    let mut ids = tx_ids(&messages);
+    ids.sort();
+    // inputs take turns in sequence numbers
+    assert_eq!(ids, vec![(1, 1), (2, 2)]);
+    let mut inputs = messages
+        .iter()
+        .filter_map(|message| match message {
+            Message::Tx(tx) => tx.input().map(str::to_owned),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    inputs.sort();
+    assert_eq!(
+        inputs,
+        vec![first.display().to_string(), second.display().to_string()]
+    );
+    // service is stopped once, after all inputs
+    assert!(matches!(messages.last(), Some(Message::Stop)));
+    assert_eq!(messages.len(), 3);
+}
+
+#[test]
+fn expand_inputs() {
+    let dir = TempPath::new("glob");
+    std::fs::create_dir_all(&dir).unwrap();
+    for name in ["b.csv", "a.csv", "c.txt"] {
+        std::fs::write(dir.join(name), "").unwrap();
+    }
+
+    let pattern = dir.join("*.csv").into_os_string();
+    let inputs = Input::expand(&["-".into(), pattern, "plain.csv".into()]).unwrap();
+    let missing = Input::expand(&[dir.join("*.json").into_os_string()]);
+
+    assert_eq!(
+        inputs,
+        vec![
+            Input::Stdin,
+            Input::File(dir.join("a.csv")),
+            Input::File(dir.join("b.csv")),
+            Input::File("plain.csv".into()),
+        ]
+    );
+    assert!(missing.is_err());
+}
+
+#[tokio::test]
+async fn reader_reads_json_lines() {
+    let data = r#"{"type": "deposit", "client": 1, "tx": 1, "amount": "1.5"}
+
+{"type": " withdrawal ", "client": "1", "tx": 2, "amount": 0.25, "timestamp": 1700000000}
+{"type": "deposit", "client": 1}
+{"type": "dispute", "client": 1, "tx": 1}
+"#;
+
+    let (summary, messages, errors) = read("input.jsonl", data.as_bytes()).await;
+
+    assert_eq!(
+        summary,
+        ReadSummary {
+            rows_read: 4,
+            rows_parsed: 3,
+            rows_rejected: 1,
+            rows_skipped: 0,
+        }
+    );
+    assert_eq!(tx_ids(&messages), vec![(1, 1), (2, 2), (1, 4)]);
+    match &messages[1] {
+        Message::Tx(tx) => {
+            assert_eq!(tx.tx_type(), TransactionType::Withdrawal);
+            assert_eq!(tx.amount(), Coin::new(25, 2));
+            assert_eq!(tx.line(), 3);
+            assert_eq!(tx.timestamp(), Some(1700000000));
+        }
+        message => panic!("unexpected message {:?}", message),
+    }
+    assert!(
+        errors.starts_with("line 4: missing field `tx`"),
+        "{}",
+        errors
+    );
+    assert!(errors.ends_with(": {\"type\": \"deposit\", \"client\": 1}\n"));
+}
+
+#[test]
+fn input_format_by_extension() {
+    assert_eq!(Input::File("a.jsonl".into()).format(), InputFormat::Jsonl);
+    assert_eq!(Input::File("a.ndjson".into()).format(), InputFormat::Jsonl);
+    assert_eq!(Input::File("a.csv".into()).format(), InputFormat::Csv);
+    assert_eq!(Input::File("a".into()).format(), InputFormat::Csv);
+    assert_eq!(Input::Stdin.format(), InputFormat::Csv);
+}
+
+async fn compress<R: tokio::io::AsyncBufRead + Unpin>(mut encoder: R) -> Vec<u8> {
+    use tokio::io::AsyncReadExt;
+    let mut out = Vec::new();
+    encoder.read_to_end(&mut out).await.unwrap();
+    out
+}
+
+#[tokio::test]
+async fn reader_decompresses_input() {
+    use async_compression::tokio::bufread::{GzipEncoder, ZstdEncoder};
+    let data = "type,client,tx,amount\ndeposit,1,1,1.0\ndeposit,1,x,1.0\n";
+    let plain = read("plain.csv", data.as_bytes()).await;
+
+    let gzip = compress(tokio::io::BufReader::new(GzipEncoder::new(data.as_bytes()))).await;
+    let zstd = compress(tokio::io::BufReader::new(ZstdEncoder::new(data.as_bytes()))).await;
+    // compression is detected by content, not by extension
+    for (name, data) in [("input.csv.gz", gzip), ("input.zst", zstd)] {
+        let (summary, messages, errors) = read(name, &data).await;
+        assert_eq!(summary, plain.0, "{}", name);
+        assert_eq!(tx_ids(&messages), tx_ids(&plain.1), "{}", name);
+        assert_eq!(errors, plain.2, "{}", name);
+    }
+}
+
+#[test]
+fn input_format_of_compressed_file() {
+    assert_eq!(
+        Input::File("a.jsonl.gz".into()).format(),
+        InputFormat::Jsonl
+    );
+    assert_eq!(Input::File("a.csv.zst".into()).format(), InputFormat::Csv);
+}
+
+fn columns(mapping: &[(&str, &str)]) -> Vec<(String, String)> {
+    mapping
+        .iter()
+        .map(|&(field, column)| (field.to_owned(), column.to_owned()))
+        .collect()
+}
+
+#[tokio::test]
+async fn reader_maps_columns() {
+    let data = "\
+kind;customer_id;txn_ref;value;type
+deposit;1;1;2.5;ignored
+withdrawal;1;2;x;ignored
+";
+
+    let config = ReaderConfig {
+        layout: CsvLayout {
+            delimiter: b';',
+            columns: columns(&[
+                ("type", "kind"),
+                ("client", "customer_id"),
+                ("tx", "txn_ref"),
+                ("amount", "value"),
+            ]),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let (summary, messages, errors) = read_with("mapped.csv", data.as_bytes(), config).await;
+
+    assert_eq!(summary.unwrap().rows_parsed, 1);
+    assert!(matches!(&messages[0], Message::Tx(tx) if tx.amount() == Coin::new(25, 1)));
+    assert!(
+        errors.ends_with(": withdrawal;1;2;x;ignored\n"),
+        "{}",
+        errors
+    );
+}
+
+#[tokio::test]
+async fn reader_fails_on_missing_column() {
+    let config = ReaderConfig {
+        layout: CsvLayout {
+            columns: columns(&[("type", "kind")]),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let (summary, messages, _) =
+        read_with("missing_column.csv", b"type,client,tx,amount\n", config).await;
+
+    assert_eq!(
+        summary.unwrap_err().to_string(),
+        "Column 'kind' not found in headers"
+    );
+    assert!(messages.is_empty());
+}
+
+#[tokio::test]
+async fn reader_reads_csv_without_headers() {
+    let data = "1\t7\tdeposit\t3.0\n2\t7\twithdrawal\t1.0\n";
+
+    let config = ReaderConfig {
+        layout: CsvLayout {
+            delimiter: b'\t',
+            has_headers: false,
+            columns: columns(&[("tx", "1"), ("client", "2"), ("type", "3")]),
+        },
+        ..Default::default()
+    };
+    let (summary, messages, _) = read_with("no_headers.tsv", data.as_bytes(), config).await;
+
+    assert_eq!(summary.unwrap().rows_parsed, 2);
+    match &messages[1] {
+        Message::Tx(tx) => {
+            assert_eq!(tx.tx_type(), TransactionType::Withdrawal);
+            assert_eq!((tx.account(), tx.id(), tx.line()), (7, 2, 2));
+            assert_eq!(tx.amount(), Coin::new(1, 0));
+        }
+        message => panic!("unexpected message {:?}", message),
+    }
+}
@@ -0,0 +1,59 @@
+use common::TempPath;
+use krct_async::primitives::*;
+
+mod common;
+
+#[tokio::test]
+async fn rejected_report_csv() {
+    let data = "\
+        type,client,tx,amount
+        deposit,1,1,1.0
+        withdrawal,1,2,1.5
+        resolve,1,1
+        ";
+
+    let accounts = common::run_tx(data.to_owned()).await;
+    let accounts = accounts.into_values().collect::<Vec<_>>();
+
+    let path = TempPath::new("rejected.csv");
+    write_rejected(&path, &accounts).unwrap();
+    let report = std::fs::read_to_string(&path).unwrap();
+
+    assert_eq!(
+        report,
+        "\
+client,tx,type,amount,input,line,reason
+1,2,withdrawal,1.5,,3,insufficient_funds
+1,1,resolve,,,4,not_disputed
+"
+    );
+}
+
+#[tokio::test]
+async fn rejected_report_json() {
+    let data = "\
+        type,client,tx,amount
+        dispute,2,7
+        ";
+
+    let accounts = common::run_tx(data.to_owned()).await;
+    let accounts = accounts.into_values().collect::<Vec<_>>();
+
+    let path = TempPath::new("rejected.json");
+    write_rejected(&path, &accounts).unwrap();
+    let report: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+
+    assert_eq!(
+        report,
+        serde_json::json!([{
+            "client": 2,
+            "tx": 7,
+            "type": "dispute",
+            "amount": null,
+            "input": null,
+            "line": 2,
+            "reason": "missing_parent"
+        }])
+    );
+}
@@ -171,6 +171,7 @@ async fn multi_acc_resolve() {
 async fn single_withdrawl_resolve() {
     let data = "\
         type,client,tx,amount
+        deposit,1,2,2.0
         withdrawal,1,1,1.1
         dispute,1,1
         resolve,1,1
@@ -179,8 +180,8 @@ async fn single_withdrawl_resolve() {
     let accounts = common::run_tx(data.to_owned()).await;
 
     let verify_account = Account::new(1)
-        .set_available(-Coin::new(11, 1))
-        .set_total(-Coin::new(11, 1));
+        .set_available(Coin::new(9, 1))
+        .set_total(Coin::new(9, 1));
 
     assert!(verify_account.check_amounts(accounts.get(&1).unwrap()));
 }
@@ -0,0 +1,115 @@
+use common::TempPath;
+use krct_async::primitives::*;
+use krct_async::snapshot::{Snapshot, SNAPSHOT_VERSION};
+use krct_async::transaction::RejectionReason;
+
+mod common;
+
+const DAY_1: &str = "\
+    type,client,tx,amount
+    deposit,1,1,10.0
+    deposit,2,2,5.0
+    withdrawal,1,3,20.0
+    dispute,2,2
+    ";
+
+const DAY_2: &str = "\
+    type,client,tx,amount
+    resolve,2,2
+    deposit,3,2,1.0
+    dispute,1,1
+    deposit,1,4,1.0
+    ";
+
+#[tokio::test]
+async fn restored_run_matches_single_run() {
+    let mut day_1 = common::run_service(DAY_1.to_owned(), |service| {
+        service.with_statement_clients([1, 2, 3])
+    })
+    .await;
+    let snapshot = day_1.snapshot().await.unwrap();
+
+    let restored = common::run_tx_with(DAY_2.to_owned(), move |service| {
+        service
+            .with_statement_clients([1, 2, 3])
+            .with_snapshot(snapshot)
+    })
+    .await;
+    let data = format!(
+        "{}{}",
+        DAY_1,
+        DAY_2.lines().skip(1).collect::<Vec<_>>().join("\n")
+    );
+    let single =
+        common::run_tx_with(data, |service| service.with_statement_clients([1, 2, 3])).await;
+
+    assert_eq!(restored.len(), 3);
+    for (id, account) in &single {
+        assert!(account.check_amounts(&restored[id]), "client {}", id);
+        assert_eq!(account.statement().len(), restored[id].statement().len());
+    }
+    // history and failed transactions are carried over
+    assert_eq!(
+        restored[&1].failed().unwrap()[0].1,
+        RejectionReason::InsufficientFunds
+    );
+    // id of client 2 from the first run is still taken
+    assert_eq!(
+        restored[&3].failed().unwrap()[0].1,
+        RejectionReason::DuplicateTransaction
+    );
+}
+
+#[tokio::test]
+async fn snapshot_file_round_trip() {
+    let mut service = common::run_service(DAY_1.to_owned(), |service| service).await;
+    let snapshot = service.snapshot().await.unwrap();
+    assert_eq!(snapshot.version, SNAPSHOT_VERSION);
+    assert_eq!(snapshot.seq, 4);
+
+    let path = TempPath::new("snapshot.json");
+    snapshot.save(&path).unwrap();
+    let loaded = Snapshot::load(&path).unwrap();
+    assert_eq!(loaded, snapshot);
+
+    // first version has no checkpoints and expired ids, they are empty
+    let mut v1 = serde_json::to_value(&snapshot).unwrap();
+    v1["version"] = 1.into();
+    for account in v1["accounts"].as_array_mut().unwrap() {
+        let account = account.as_object_mut().unwrap();
+        account.remove("checkpoint");
+        account.remove("expired");
+    }
+    std::fs::write(&path, v1.to_string()).unwrap();
+    assert_eq!(Snapshot::load(&path).unwrap(), snapshot);
+
+    for version in [0, SNAPSHOT_VERSION + 1] {
+        let other = serde_json::json!({ "version": version, "accounts": "other format" });
+        std::fs::write(&path, other.to_string()).unwrap();
+        let err = Snapshot::load(&path).unwrap_err().to_string();
+        assert_eq!(
+            err,
+            format!("Unsupported snapshot version {}, expected 1 to 2", version)
+        );
+    }
+}
+
+#[tokio::test]
+async fn restored_accounts_get_current_limits() {
+    let mut day_1 = common::run_service(DAY_1.to_owned(), |service| service).await;
+    let snapshot = day_1.snapshot().await.unwrap();
+
+    let data = "\
+        type,client,tx,amount
+        withdrawal,1,5,12.0
+        ";
+    let limits = [(1, Coin::new(5, 0))].into();
+    let accounts = common::run_tx_with(data.to_owned(), move |service| {
+        service
+            .with_snapshot(snapshot)
+            .with_overdraft_limits(limits)
+    })
+    .await;
+
+    assert_eq!(accounts[&1].available(), -Coin::new(2, 0));
+}
@@ -0,0 +1,115 @@
+use krct_async::primitives::*;
+use krct_async::transaction::TransactionType;
+
+mod common;
+
+#[tokio::test]
+async fn statement_running_balances() {
+    let data = "\
+        type,client,tx,amount
+        deposit,1,1,10.0
+        withdrawal,1,2,3.0
+        dispute,1,1,4.0
+        withdrawal,1,3,20.0
+        chargeback,1,1
+        unlock,1,0
+        ";
+
+    let accounts = common::run_tx_with(data.to_owned(), |service| {
+        service.with_statement_clients([1])
+    })
+    .await;
+    let statement = accounts[&1].statement();
+
+    let rows = statement
+        .iter()
+        .map(|entry| {
+            (
+                entry.tx.tx_type(),
+                entry.amount,
+                entry.available,
+                entry.held,
+                entry.total,
+                entry.locked,
+            )
+        })
+        .collect::<Vec<_>>();
+    // rejected withdrawal is not in the statement
+    assert_eq!(
+        rows,
+        vec![
+            (
+                TransactionType::Deposit,
+                Some(Coin::new(10, 0)),
+                Coin::new(10, 0),
+                Coin::new(0, 0),
+                Coin::new(10, 0),
+                false
+            ),
+            (
+                TransactionType::Withdrawal,
+                Some(Coin::new(3, 0)),
+                Coin::new(7, 0),
+                Coin::new(0, 0),
+                Coin::new(7, 0),
+                false
+            ),
+            (
+                TransactionType::Dispute,
+                Some(Coin::new(4, 0)),
+                Coin::new(3, 0),
+                Coin::new(4, 0),
+                Coin::new(7, 0),
+                false
+            ),
+            (
+                TransactionType::Chargeback,
+                Some(Coin::new(4, 0)),
+                Coin::new(3, 0),
+                Coin::new(0, 0),
+                Coin::new(3, 0),
+                true
+            ),
+            (
+                TransactionType::Unlock,
+                None,
+                Coin::new(3, 0),
+                Coin::new(0, 0),
+                Coin::new(3, 0),
+                false
+            ),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn statement_csv_for_selected_clients() {
+    let data = "\
+        type,client,tx,amount
+        deposit,2,1,1.5
+        deposit,1,2,2.0
+        deposit,3,3,1.0
+        dispute,2,1
+        ";
+
+    let accounts = common::run_tx_with(data.to_owned(), |service| {
+        service.with_statement_clients([2, 1])
+    })
+    .await;
+    // statement is kept only for selected clients
+    assert!(accounts[&3].statement().is_empty());
+    let accounts = accounts.into_values().collect::<Vec<_>>();
+
+    let mut out = Vec::new();
+    write_statement_to(&mut out, &accounts, &[2, 1, 9], OutputFormat::Csv).unwrap();
+
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "\
+client,tx,type,amount,input,line,available,held,total,locked
+1,2,deposit,2.0,,3,2.0,0.0000,2.0,false
+2,1,deposit,1.5,,2,1.5,0.0000,1.5,false
+2,1,dispute,1.5,,5,0.0,1.5,1.5,false
+"
+    );
+}
@@ -0,0 +1,98 @@
+use common::TempPath;
+use krct_async::primitives::*;
+use krct_async::store::StoreBackend;
+use krct_async::transaction::RejectionReason;
+
+mod common;
+
+const DATA: &str = "\
+    type,client,tx,amount
+    deposit,1,1,10.0
+    deposit,2,2,5.0
+    withdrawal,1,3,20.0
+    dispute,2,2
+    deposit,3,2,1.0
+    dispute,1,1,4.0
+    resolve,2,2
+    chargeback,1,1
+    deposit,1,4,1.0
+    ";
+
+/// Backend in a temp dir, which is removed with the returned path
+fn sled_backend(name: &str) -> (StoreBackend, TempPath) {
+    let path = TempPath::new(name);
+    (StoreBackend::sled(&path).unwrap(), path)
+}
+
+#[tokio::test]
+async fn sled_store_matches_memory_store() {
+    let memory = common::run_tx(DATA.to_owned()).await;
+    let (backend, _path) = sled_backend("store_matches.sled");
+    let sled =
+        common::run_tx_with(DATA.to_owned(), move |service| service.with_store(backend)).await;
+
+    assert_eq!(sled.len(), 3);
+    for (id, account) in &memory {
+        assert_eq!(
+            account.state().unwrap(),
+            sled[id].state().unwrap(),
+            "client {}",
+            id
+        );
+        assert_eq!(account, &sled[id], "client {}", id);
+    }
+    assert_eq!(
+        common::reasons(&sled[&1]),
+        vec![
+            (3, RejectionReason::InsufficientFunds),
+            (4, RejectionReason::AccountLocked)
+        ]
+    );
+}
+
+#[tokio::test]
+async fn restored_history_moves_to_sled_store() {
+    let mut day_1 = common::run_service(DATA.to_owned(), |service| service).await;
+    let snapshot = day_1.snapshot().await.unwrap();
+    let day_1_failed = day_1.get_accounts().await[&3]
+        .failed()
+        .unwrap()
+        .into_owned();
+
+    let data = "\
+        type,client,tx,amount
+        dispute,2,2,2.0
+        chargeback,2,2
+        deposit,3,2,1.0
+        ";
+    let (backend, _path) = sled_backend("store_restored.sled");
+    let accounts = common::run_tx_with(data.to_owned(), move |service| {
+        service.with_snapshot(snapshot).with_store(backend)
+    })
+    .await;
+
+    assert_eq!(accounts[&2].total(), Coin::new(3, 0));
+    assert!(accounts[&2].is_locked());
+    // failure of the first run is kept before the new one
+    let failed = accounts[&3].failed().unwrap();
+    assert_eq!(failed.len(), 2);
+    assert!(failed
+        .iter()
+        .all(|(tx, reason)| tx.id() == 2 && *reason == RejectionReason::DuplicateTransaction));
+    assert_eq!(failed[0], day_1_failed[0]);
+    assert_ne!(failed[1], day_1_failed[0]);
+}
+
+#[tokio::test]
+async fn sled_account_clones_share_history() {
+    let (backend, _path) = sled_backend("store_clones.sled");
+    let accounts =
+        common::run_tx_with(DATA.to_owned(), move |service| service.with_store(backend)).await;
+    let (tx, reason) = accounts[&3].failed().unwrap()[0].clone();
+
+    let mut copy = accounts[&3].clone();
+    copy.reject(&tx, reason).unwrap();
+    // failure made through the clone is appended after the first one, and is seen by the original
+    assert_eq!(copy.failed().unwrap().len(), 2);
+    assert_eq!(accounts[&3].failed().unwrap(), copy.failed().unwrap());
+}
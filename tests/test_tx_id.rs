@@ -0,0 +1,114 @@
+use krct_async::account::Account;
+use krct_async::primitives::*;
+use krct_async::transaction::RejectionReason;
+
+mod common;
+
+#[tokio::test]
+async fn duplicate_deposit_other_client() {
+    let data = "\
+        type,client,tx,amount
+        deposit,1,1,1.1
+        deposit,2,1,2.2
+        withdrawal,3,1,0.0
+        ";
+
+    let accounts = common::run_tx(data.to_owned()).await;
+
+    let verify_account = Account::new(1)
+        .set_available(Coin::new(11, 1))
+        .set_total(Coin::new(11, 1));
+
+    assert!(verify_account.check_amounts(accounts.get(&1).unwrap()));
+    assert!(Account::new(2).check_amounts(accounts.get(&2).unwrap()));
+    assert!(Account::new(3).check_amounts(accounts.get(&3).unwrap()));
+
+    assert_eq!(
+        common::reasons(accounts.get(&2).unwrap()),
+        vec![(1, RejectionReason::DuplicateTransaction)]
+    );
+    assert_eq!(
+        common::reasons(accounts.get(&3).unwrap()),
+        vec![(1, RejectionReason::DuplicateTransaction)]
+    );
+}
+
+#[tokio::test]
+async fn cross_client_dispute() {
+    let data = "\
+        type,client,tx,amount
+        deposit,1,1,1.1
+        deposit,2,2,2.2
+        dispute,2,1
+        resolve,2,1
+        chargeback,2,1
+        dispute,1,1
+        ";
+
+    let accounts = common::run_tx(data.to_owned()).await;
+
+    let verify_account = Account::new(1)
+        .set_held(Coin::new(11, 1))
+        .set_total(Coin::new(11, 1));
+
+    assert!(verify_account.check_amounts(accounts.get(&1).unwrap()));
+
+    let verify_account = Account::new(2)
+        .set_available(Coin::new(22, 1))
+        .set_total(Coin::new(22, 1));
+
+    assert!(verify_account.check_amounts(accounts.get(&2).unwrap()));
+    assert_eq!(
+        common::reasons(accounts.get(&2).unwrap()),
+        vec![
+            (1, RejectionReason::CrossClientReference),
+            (1, RejectionReason::CrossClientReference),
+            (1, RejectionReason::CrossClientReference)
+        ]
+    );
+}
+
+#[tokio::test]
+async fn dispute_unknown_id_is_missing_parent() {
+    let data = "\
+        type,client,tx,amount
+        dispute,1,5
+        ";
+
+    let accounts = common::run_tx(data.to_owned()).await;
+
+    assert_eq!(
+        common::reasons(accounts.get(&1).unwrap()),
+        vec![(5, RejectionReason::MissingParent)]
+    );
+}
+
+#[tokio::test]
+async fn rejected_transaction_releases_id() {
+    let data = "\
+        type,client,tx,amount
+        withdrawal,1,1,5.0
+        deposit,2,1,2.0
+        dispute,1,1
+        deposit,3,1,1.0
+        ";
+
+    let accounts = common::run_tx(data.to_owned()).await;
+
+    let verify_account = Account::new(2)
+        .set_available(Coin::new(2, 0))
+        .set_total(Coin::new(2, 0));
+    assert!(verify_account.check_amounts(accounts.get(&2).unwrap()));
+
+    assert_eq!(
+        common::reasons(accounts.get(&1).unwrap()),
+        vec![
+            (1, RejectionReason::InsufficientFunds),
+            (1, RejectionReason::CrossClientReference)
+        ]
+    );
+    assert_eq!(
+        common::reasons(accounts.get(&3).unwrap()),
+        vec![(1, RejectionReason::DuplicateTransaction)]
+    );
+}
@@ -0,0 +1,233 @@
+use common::TempPath;
+use krct_async::account::Account;
+use krct_async::primitives::*;
+use krct_async::service::Service;
+use krct_async::store::StoreBackend;
+use krct_async::transaction::RejectionReason;
+use krct_async::wal::{Processed, Wal};
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::Path;
+use tokio::sync::mpsc;
+
+mod common;
+
+const DATA: &str = "\
+type,client,tx,amount
+deposit,1,1,10.0
+deposit,2,2,5.0
+withdrawal,1,3,20.0
+dispute,2,2
+deposit,x,4,1.0
+deposit,3,2,1.0
+resolve,2,2
+dispute,1,1
+deposit,1,5,1.0
+chargeback,1,1
+";
+
+/// Run reader and service configured by `configure` over `input` with WAL at `wal_path`,
+/// replaying records which are already in it
+async fn run_with_wal(
+    input: &Path,
+    wal_path: &Path,
+    configure: impl FnOnce(Service) -> Service,
+) -> anyhow::Result<(ReadSummary, BTreeMap<AccountID, Account>)> {
+    let (wal, records) = Wal::open(wal_path)?;
+    let config = ReaderConfig {
+        processed: records.iter().map(|record| record.tx.seq()).collect(),
+        ..Default::default()
+    };
+    let (sender, receiver) = mpsc::channel(CHANNEL_BUUFER_SIZE);
+    let mut service = configure(Service::new(receiver));
+    service.recover(records)?;
+    let mut service = service.with_wal(wal);
+
+    let input = input.to_owned();
+    let reader = tokio::spawn(async move {
+        run_reader(input.into(), sender, &mut std::io::sink(), &config).await
+    });
+    service.run().await?;
+    let summary = reader.await??;
+    Ok((summary, service.get_accounts().await))
+}
+
+#[tokio::test]
+async fn recovered_run_matches_single_run() {
+    let input = TempPath::with_data("wal_input.csv", DATA);
+    let full_wal = TempPath::new("full.wal");
+    let (summary, single) = run_with_wal(&input, &full_wal, |service| {
+        service.with_statement_clients([1, 2, 3])
+    })
+    .await
+    .unwrap();
+    assert_eq!(summary.rows_skipped, 0);
+
+    // interrupted run logged transactions of clients 1 and 2 up to the dispute of client 1,
+    // but not the deposit of client 3, and was killed in the middle of the next record
+    let (_, records) = Wal::open(&full_wal).unwrap();
+    assert_eq!(records.len(), 9);
+    let wal = TempPath::new("interrupted.wal");
+    let mut file = std::fs::File::create(&wal).unwrap();
+    for record in records
+        .iter()
+        .filter(|record| record.tx.seq() <= 8 && record.tx.account() != 3)
+    {
+        writeln!(file, "{}", serde_json::to_string(record).unwrap()).unwrap();
+    }
+    write!(file, "{{\"tx\":{{\"tx_type\":").unwrap();
+    drop(file);
+
+    let (summary, recovered) = run_with_wal(&input, &wal, |service| {
+        service.with_statement_clients([1, 2, 3])
+    })
+    .await
+    .unwrap();
+    assert_eq!(summary.rows_skipped, 6);
+    assert_eq!(recovered.len(), single.len());
+    for (id, account) in &single {
+        assert!(account.check_amounts(&recovered[id]), "client {}", id);
+        assert_eq!(account.failed().unwrap(), recovered[id].failed().unwrap());
+        assert_eq!(account.statement().len(), recovered[id].statement().len());
+    }
+    assert_eq!(
+        recovered[&3].failed().unwrap()[0].1,
+        RejectionReason::DuplicateTransaction
+    );
+
+    // torn record is dropped and the rest of the run is appended
+    let (_, mut logged) = Wal::open(&wal).unwrap();
+    logged.sort_by_key(|record| record.tx.seq());
+    let mut expected = records.clone();
+    expected.sort_by_key(|record| record.tx.seq());
+    assert_eq!(logged, expected);
+}
+
+#[tokio::test]
+async fn recovery_with_gap_before_rejected_id() {
+    let input = TempPath::with_data(
+        "wal_gap.csv",
+        "type,client,tx,amount\ndeposit,1,9,1.0\ndeposit,2,9,2.0\n",
+    );
+    let full_wal = TempPath::new("gap_full.wal");
+    let (_, single) = run_with_wal(&input, &full_wal, |service| service)
+        .await
+        .unwrap();
+
+    // only the rejection of the duplicate id was logged, not the deposit which took the id
+    let (_, records) = Wal::open(&full_wal).unwrap();
+    let wal = TempPath::new("gap.wal");
+    let mut file = std::fs::File::create(&wal).unwrap();
+    for record in records.iter().filter(|record| record.tx.seq() == 2) {
+        assert!(record.by_service);
+        writeln!(file, "{}", serde_json::to_string(record).unwrap()).unwrap();
+    }
+    drop(file);
+
+    let (summary, recovered) = run_with_wal(&input, &wal, |service| service).await.unwrap();
+    assert_eq!(summary.rows_skipped, 1);
+    assert_eq!(recovered.len(), single.len());
+    for (id, account) in &single {
+        assert!(account.check_amounts(&recovered[id]), "client {}", id);
+        assert_eq!(account.failed().unwrap(), recovered[id].failed().unwrap());
+    }
+    assert_eq!(
+        recovered[&2].failed().unwrap()[0].1,
+        RejectionReason::DuplicateTransaction
+    );
+}
+
+#[tokio::test]
+async fn recovery_with_sled_store_keeps_failures_once() {
+    let input = TempPath::with_data("wal_sled.csv", DATA);
+    let full_wal = TempPath::new("sled_full.wal");
+    let (_, single) = run_with_wal(&input, &full_wal, |service| service)
+        .await
+        .unwrap();
+
+    // interrupted run logged rows up to the dispute of client 1, recovered accounts get new rows after it
+    let (_, records) = Wal::open(&full_wal).unwrap();
+    let wal = TempPath::new("sled.wal");
+    let mut file = std::fs::File::create(&wal).unwrap();
+    for record in records.iter().filter(|record| record.tx.seq() <= 8) {
+        writeln!(file, "{}", serde_json::to_string(record).unwrap()).unwrap();
+    }
+    drop(file);
+
+    let store = TempPath::new("wal.sled");
+    let backend = StoreBackend::sled(&store).unwrap();
+    let (_, recovered) = run_with_wal(&input, &wal, |service| service.with_store(backend))
+        .await
+        .unwrap();
+    for (id, account) in &single {
+        assert!(account.check_amounts(&recovered[id]), "client {}", id);
+        assert_eq!(
+            account.failed().unwrap(),
+            recovered[id].failed().unwrap(),
+            "client {}",
+            id
+        );
+    }
+}
+
+#[tokio::test]
+async fn recovery_fails_if_replay_diverges() {
+    let input = TempPath::with_data("wal_diverged.csv", DATA);
+    let wal = TempPath::new("diverged.wal");
+    run_with_wal(&input, &wal, |service| service).await.unwrap();
+
+    // withdrawal rejected in the log is applied with overdraft limit
+    let limits = [(1, Coin::new(100, 0))].into();
+    let err = run_with_wal(&input, &wal, |service| {
+        service.with_overdraft_limits(limits)
+    })
+    .await
+    .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "WAL replay diverged on transaction 3 of client 1 at line 4: None instead of Some(InsufficientFunds)"
+    );
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn failed_account_releases_queued_claims() {
+    // input fits in the channel, so all deposits of client 1 are queued before its task fails on the first one,
+    // and client 2 references the last of them
+    let mut data = String::from("type,client,tx,amount\n");
+    for id in 1..=50 {
+        data += &format!("deposit,1,{},1.0\n", id);
+    }
+    data += "dispute,2,50,\n";
+    let input = TempPath::with_data("wal_failed.csv", data);
+
+    let file = std::fs::OpenOptions::new()
+        .write(true)
+        .open("/dev/full")
+        .unwrap();
+    let (sender, receiver) = mpsc::channel(CHANNEL_BUUFER_SIZE);
+    let mut service = Service::new(receiver).with_wal(Wal::from_file(file));
+    let reader_input = input.to_path_buf();
+    let reader = tokio::spawn(async move {
+        run_reader(
+            reader_input.into(),
+            sender,
+            &mut std::io::sink(),
+            &ReaderConfig::default(),
+        )
+        .await
+    });
+    let res = tokio::time::timeout(std::time::Duration::from_secs(10), service.run())
+        .await
+        .expect("service waits for claims of the failed account");
+    assert_eq!(res.unwrap_err().to_string(), "failed to write WAL");
+    let _ = reader.await.unwrap(); // reader fails as the service stopped
+}
+
+#[test]
+fn processed_with_gaps() {
+    let processed: Processed = [1, 2, 3, 5, 8].into_iter().collect();
+    assert_eq!(processed.up_to, 3);
+    assert!(processed.contains(2) && processed.contains(5) && processed.contains(8));
+    assert!(!processed.contains(4) && !processed.contains(6) && !processed.contains(9));
+}
@@ -1,5 +1,7 @@
 use krct_async::account::Account;
 use krct_async::primitives::*;
+use krct_async::transaction::RejectionReason;
+use std::collections::HashMap;
 
 mod common;
 
@@ -7,14 +9,15 @@ mod common;
 async fn single_withdrawal() {
     let data = "\
         type,client,tx,amount
-        withdrawal,1,1,1.1
+        deposit,1,1,2.0
+        withdrawal,1,2,1.1
         ";
 
     let accounts = common::run_tx(data.to_owned()).await;
 
     let verify_account = Account::new(1)
-        .set_available(-Coin::new(11, 1))
-        .set_total(-Coin::new(11, 1));
+        .set_available(Coin::new(9, 1))
+        .set_total(Coin::new(9, 1));
 
     assert!(verify_account.check_amounts(accounts.get(&1).unwrap()));
 }
@@ -23,15 +26,16 @@ async fn single_withdrawal() {
 async fn succesfull_withdrawal_multi() {
     let data = "\
         type,client,tx,amount
-        withdrawal,1,1,1.1
-        withdrawal,1,2,2.2222  "
+        deposit,1,1,5.0
+        withdrawal,1,2,1.1
+        withdrawal,1,3,2.2222  "
         .to_owned();
 
     let accounts = common::run_tx(data.to_owned()).await;
 
     let verify_account = Account::new(1)
-        .set_available(-Coin::new(33222, 4))
-        .set_total(-Coin::new(33222, 4));
+        .set_available(Coin::new(16778, 4))
+        .set_total(Coin::new(16778, 4));
 
     assert!(verify_account.check_amounts(accounts.get(&1).unwrap()));
 }
@@ -40,15 +44,16 @@ async fn succesfull_withdrawal_multi() {
 async fn duplicate_withdrawal() {
     let data = "\
         type,client,tx,amount
-        withdrawal,1,1,1.1
-        withdrawal,1,1,1.1  "
+        deposit,1,1,5.0
+        withdrawal,1,2,1.1
+        withdrawal,1,2,1.1  "
         .to_owned();
 
     let accounts = common::run_tx(data.to_owned()).await;
 
     let verify_account = Account::new(1)
-        .set_available(-Coin::new(11, 1))
-        .set_total(-Coin::new(11, 1));
+        .set_available(Coin::new(39, 1))
+        .set_total(Coin::new(39, 1));
 
     assert!(verify_account.check_amounts(accounts.get(&1).unwrap()));
 }
@@ -57,21 +62,150 @@ async fn duplicate_withdrawal() {
 async fn succesfull_withdrawal_two_acc() {
     let data = "\
         type,client,tx,amount
-        withdrawal,1,1,1.1
-        withdrawal,2,2,2.2  "
+        deposit,1,1,5.0
+        deposit,2,2,5.0
+        withdrawal,1,3,1.1
+        withdrawal,2,4,2.2  "
         .to_owned();
 
     let accounts = common::run_tx(data.to_owned()).await;
 
     let verify_account = Account::new(1)
-        .set_available(-Coin::new(11, 1))
-        .set_total(-Coin::new(11, 1));
+        .set_available(Coin::new(39, 1))
+        .set_total(Coin::new(39, 1));
+
+    assert!(verify_account.check_amounts(accounts.get(&1).unwrap()));
+
+    let verify_account = Account::new(2)
+        .set_available(Coin::new(28, 1))
+        .set_total(Coin::new(28, 1));
+
+    assert!(verify_account.check_amounts(accounts.get(&2).unwrap()));
+}
+
+#[tokio::test]
+async fn withdrawal_without_funds() {
+    let data = "\
+        type,client,tx,amount
+        withdrawal,1,1,1.1
+        ";
+
+    let accounts = common::run_tx(data.to_owned()).await;
+
+    let verify_account = Account::new(1);
+
+    assert!(verify_account.check_amounts(accounts.get(&1).unwrap()));
+}
+
+#[tokio::test]
+async fn withdrawal_exceeds_available() {
+    let data = "\
+        type,client,tx,amount
+        deposit,1,1,1.0
+        withdrawal,1,2,1.1
+        withdrawal,1,3,0.4
+        ";
+
+    let accounts = common::run_tx(data.to_owned()).await;
+
+    let verify_account = Account::new(1)
+        .set_available(Coin::new(6, 1))
+        .set_total(Coin::new(6, 1));
+
+    assert!(verify_account.check_amounts(accounts.get(&1).unwrap()));
+}
+
+#[tokio::test]
+async fn withdrawal_exceeds_held_funds() {
+    let data = "\
+        type,client,tx,amount
+        deposit,1,1,1.0
+        dispute,1,1
+        withdrawal,1,2,0.5
+        ";
+
+    let accounts = common::run_tx(data.to_owned()).await;
+
+    let verify_account = Account::new(1)
+        .set_held(Coin::new(1, 0))
+        .set_total(Coin::new(1, 0));
+
+    assert!(verify_account.check_amounts(accounts.get(&1).unwrap()));
+}
+
+#[tokio::test]
+async fn withdrawal_within_overdraft_limit() {
+    let data = "\
+        type,client,tx,amount
+        deposit,1,1,1.0
+        withdrawal,1,2,1.5
+        withdrawal,2,3,2.0
+        ";
+
+    let limits = HashMap::from([(1, Coin::new(5, 1)), (2, Coin::new(2, 0))]);
+    let accounts = common::run_tx_with(data.to_owned(), |service| {
+        service.with_overdraft_limits(limits)
+    })
+    .await;
+
+    let verify_account = Account::new(1)
+        .set_available(-Coin::new(5, 1))
+        .set_total(-Coin::new(5, 1));
 
     assert!(verify_account.check_amounts(accounts.get(&1).unwrap()));
 
     let verify_account = Account::new(2)
-        .set_available(-Coin::new(22, 1))
-        .set_total(-Coin::new(22, 1));
+        .set_available(-Coin::new(2, 0))
+        .set_total(-Coin::new(2, 0));
 
     assert!(verify_account.check_amounts(accounts.get(&2).unwrap()));
 }
+
+#[tokio::test]
+async fn withdrawal_exceeds_overdraft_limit() {
+    let data = "\
+        type,client,tx,amount
+        deposit,1,1,1.0
+        withdrawal,1,2,1.6
+        withdrawal,1,3,1.5
+        withdrawal,2,4,0.1
+        ";
+
+    let limits = HashMap::from([(1, Coin::new(5, 1))]);
+    let accounts = common::run_tx_with(data.to_owned(), |service| {
+        service.with_overdraft_limits(limits)
+    })
+    .await;
+
+    // withdrawal 2 is rejected, withdrawal 3 reaches the limit exactly
+    let verify_account = Account::new(1)
+        .set_available(-Coin::new(5, 1))
+        .set_total(-Coin::new(5, 1));
+
+    assert!(verify_account.check_amounts(accounts.get(&1).unwrap()));
+
+    // accounts without limit can't go below zero
+    let verify_account = Account::new(2);
+
+    assert!(verify_account.check_amounts(accounts.get(&2).unwrap()));
+}
+
+#[tokio::test]
+async fn withdrawal_rejection_reason() {
+    let data = "\
+        type,client,tx,amount
+        deposit,1,1,1.0
+        withdrawal,1,2,1.1
+        withdrawal,1,1,0.5
+        ";
+
+    let accounts = common::run_tx(data.to_owned()).await;
+
+    assert_eq!(
+        common::reasons(&accounts[&1]),
+        vec![
+            (2, RejectionReason::InsufficientFunds),
+            (1, RejectionReason::DuplicateTransaction)
+        ]
+    );
+}